@@ -0,0 +1,345 @@
+use crate::{base::{Game, Board, Step, Player, GameState, GameType}, ai::{self, AI, Level}, mcts::Mcts,
+    solver::Oracle, record, net::{Peer, Message}};
+
+mod external;
+
+pub use external::{External, Protocol, MOVE_TIME};
+
+/// 对方提出、等着答复的请求
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Request {
+    /// 撤回提出方的上一步
+    Undo,
+    Draw,
+}
+
+/// 一个座位做的事
+#[derive(PartialEq, Clone, Debug)]
+pub enum Action<S> {
+    Step(S),
+    Resign,
+    /// 提出悔棋或求和
+    Ask(Request),
+    /// 答复对方的请求
+    Reply(Request, bool),
+    Chat(String),
+    /// 离开对局，如对方断开、外部程序退出
+    Leave(String),
+}
+
+/// 界面收到的输入，交给本地的人
+#[derive(PartialEq, Clone, Debug)]
+pub enum Input {
+    None,
+    Click((u8, u8)),
+    Key(char),
+    /// 输入完的一行聊天
+    Text(String),
+}
+
+/// 座位：本地的人、电脑、联网的对方等。界面循环只通过它拿到每个座位的动作
+pub trait Agent<B: Board<S>, S: Step> {
+    /// 界面循环每次都会问每个座位，me为这个座位执哪一方。轮到自己时给出走法，
+    /// 随时可以认输、提请求、聊天；asked为对方提出、等自己答复的请求。没有动作时返回None
+    fn poll(&mut self, game: &Game<B, S>, me: Player, input: &Input, asked: Option<Request>) -> Option<Action<S>>;
+
+    /// 另一方做了action，已经作用到game上
+    fn observe(&mut self, _game: &Game<B, S>, _action: &Action<S>) {}
+
+    /// 显示用的名字
+    fn name(&self) -> String;
+
+    /// 本地的人才接收界面输入，轮到时显示提示与威胁
+    fn is_local_human(&self) -> bool {
+        false
+    }
+
+    /// 局面被编辑替换后还能接着下，联网的对方不知道本地改了局面，不能
+    fn can_edit(&self) -> bool {
+        true
+    }
+}
+
+/// 本地用鼠标和键盘下棋的人：点击落子（走棋类先点起点再点终点），
+/// r认输，d求和，u悔棋，y/n答复对方的请求
+#[derive(Default)]
+pub struct Human {
+    from: Option<(u8, u8)>,
+}
+
+impl Human {
+    pub fn new() -> Self {
+        Human::default()
+    }
+}
+
+impl<B: Board<S>, S: Step> Agent<B, S> for Human {
+    fn poll(&mut self, game: &Game<B, S>, me: Player, input: &Input, asked: Option<Request>) -> Option<Action<S>> {
+        let my_turn = game.curr_player == me && game.state == GameState::Running;
+        match input {
+            Input::Click(pos) if my_turn => match (game.game_type == GameType::Put, self.from.take()) {
+                (true, _) => Some(Action::Step(S::new_put_step(*pos, me))),
+                (false, None) => {
+                    self.from = Some(*pos);
+                    None
+                },
+                (false, Some(from)) => Some(Action::Step(S::new_move_step(from, *pos, me))),
+            },
+            Input::Key('r') if game.state == GameState::Running => Some(Action::Resign),
+            Input::Key('d') if game.state == GameState::Running => Some(Action::Ask(Request::Draw)),
+            Input::Key('u') => Some(Action::Ask(Request::Undo)),
+            Input::Key(c @ ('y' | 'n')) => asked.map(|r| Action::Reply(r, *c == 'y')),
+            Input::Text(text) => Some(Action::Chat(text.clone())),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> String {
+        "人".to_string()
+    }
+
+    fn is_local_human(&self) -> bool {
+        true
+    }
+}
+
+/// 内置的电脑。有完美走法表时全力模式按表走；蒙特卡洛的搜索树走棋之间保留
+pub struct Computer<'a, B, S> {
+    pub level: Level,
+    oracle: Option<&'a dyn Oracle<B, S>>,
+    tree: Mcts<B, S>,
+}
+
+impl<'a, B: AI<S>, S: Step> Computer<'a, B, S> {
+    pub fn new(level: Level, oracle: Option<&'a dyn Oracle<B, S>>) -> Self {
+        Computer { level, oracle, tree: Mcts::new() }
+    }
+}
+
+impl<B: AI<S>, S: Step> Agent<B, S> for Computer<'_, B, S> {
+    fn poll(&mut self, game: &Game<B, S>, me: Player, _input: &Input, asked: Option<Request>) -> Option<Action<S>> {
+        match asked {
+            // 悔棋总是同意；求和时自己不占优才同意
+            Some(Request::Undo) => return Some(Action::Reply(Request::Undo, true)),
+            Some(Request::Draw) => return Some(Action::Reply(Request::Draw, game.board.score(me) <= 0)),
+            None => (),
+        }
+        if game.curr_player != me || game.state != GameState::Running {
+            return None;
+        }
+        // 降低难度时不查完美走法表
+        let level = ai::timed_level(game, self.level);
+        self.oracle.filter(|_| level.is_full())
+            .and_then(|o| o.best_step(&game.board, me))
+            .or_else(|| ai::get_game_best_step(game, &level, &mut self.tree))
+            .map(Action::Step)
+    }
+
+    fn name(&self) -> String {
+        format!("电脑(深度{})", self.level.depth)
+    }
+}
+
+/// 联网的对方，协议见net.rs。本方的动作转发过去，对方的消息变成动作
+pub struct Remote {
+    peer: Option<Peer>,
+}
+
+impl Remote {
+    pub fn new(peer: Peer) -> Self {
+        Remote { peer: Some(peer) }
+    }
+
+    fn send(&mut self, message: Message) {
+        if let Some(p) = &self.peer {
+            let _ = p.send(&message);
+        }
+    }
+}
+
+impl<B: Board<S>, S: Step> Agent<B, S> for Remote {
+    fn poll(&mut self, _game: &Game<B, S>, me: Player, _input: &Input, _asked: Option<Request>) -> Option<Action<S>> {
+        let peer = self.peer.as_ref()?;
+        let message = match peer.poll() {
+            Ok(m) => m?,
+            Err(e) => {
+                self.peer = None;
+                return Some(Action::Leave(e.to_string()));
+            },
+        };
+        match message {
+            // 是否轮到、是否合法由界面循环判断
+            Message::Move(text) => record::parse_step::<S>(&text, me).map(Action::Step),
+            Message::Chat(text) => Some(Action::Chat(text)),
+            Message::Resign => Some(Action::Resign),
+            Message::Undo => Some(Action::Ask(Request::Undo)),
+            Message::UndoReply(ok) => Some(Action::Reply(Request::Undo, ok)),
+            Message::Draw => Some(Action::Ask(Request::Draw)),
+            Message::DrawReply(ok) => Some(Action::Reply(Request::Draw, ok)),
+            Message::Setup { .. } => None,
+        }
+    }
+
+    fn observe(&mut self, _game: &Game<B, S>, action: &Action<S>) {
+        let message = match action {
+            Action::Step(s) => Message::Move(record::step_to_string(s)),
+            Action::Resign => Message::Resign,
+            Action::Ask(Request::Undo) => Message::Undo,
+            Action::Ask(Request::Draw) => Message::Draw,
+            Action::Reply(Request::Undo, ok) => Message::UndoReply(*ok),
+            Action::Reply(Request::Draw, ok) => Message::DrawReply(*ok),
+            Action::Chat(text) => Message::Chat(text.clone()),
+            Action::Leave(_) => {
+                self.peer = None;
+                return;
+            },
+        };
+        self.send(message);
+    }
+
+    fn name(&self) -> String {
+        "对方".to_string()
+    }
+
+    fn can_edit(&self) -> bool {
+        false
+    }
+}
+
+/// 在game上执行me的动作，request为悬而未决的请求（提出方，内容），随之更新。
+/// 返回动作是否生效，生效的动作应当告诉另一方
+pub fn apply<B: Board<S>, S: Step>(game: &mut Game<B, S>, me: Player, action: &Action<S>,
+    request: &mut Option<(Player, Request)>) -> bool {
+
+    match action {
+        Action::Step(s) => game.curr_player == me && s.who() == me && game.step(*s),
+        Action::Resign => {
+            let running = game.state == GameState::Running;
+            game.resign(me);
+            running
+        },
+        Action::Ask(r) => {
+            if request.is_some() {
+                return false;
+            }
+            *request = Some((me, *r));
+            true
+        },
+        Action::Reply(r, ok) => match *request {
+            Some((from, asked)) if from != me && asked == *r => {
+                *request = None;
+                if *ok {
+                    match r {
+                        Request::Undo => {
+                            game.undo(from);
+                        },
+                        Request::Draw => game.agree_draw(),
+                    }
+                }
+                true
+            },
+            _ => false,
+        },
+        // 离开的一方按认输处理
+        Action::Leave(_) => {
+            game.resign(me);
+            true
+        },
+        Action::Chat(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{base::{GameType, OutCome}, tic_tac_toe::{TTTBoard, TTTStep}};
+
+    fn game() -> Game<TTTBoard, TTTStep> {
+        Game::new(TTTBoard::new(), GameType::Put)
+    }
+
+    #[test]
+    fn human_maps_input() {
+        let g = game();
+        let mut h = Human::new();
+        let poll = |h: &mut Human, me, input| Agent::<TTTBoard, TTTStep>::poll(h, &g, Player(me), &input, None);
+        assert!(matches!(poll(&mut h, 0, Input::Click((1, 1))), Some(Action::Step(s)) if s.to_pos() == (1, 1)));
+        // 没轮到时点击不算
+        assert!(poll(&mut h, 1, Input::Click((1, 1))).is_none());
+        assert!(matches!(poll(&mut h, 1, Input::Key('r')), Some(Action::Resign)));
+        assert!(poll(&mut h, 0, Input::Key('y')).is_none());
+        let asked = Agent::<TTTBoard, TTTStep>::poll(&mut h, &g, Player(0), &Input::Key('y'), Some(Request::Draw));
+        assert!(matches!(asked, Some(Action::Reply(Request::Draw, true))));
+    }
+
+    #[test]
+    fn requests_need_matching_reply() {
+        let mut g = game();
+        let mut request = None;
+        let s = |p: u8, pos| Action::Step(TTTStep::new_put_step(pos, Player(p)));
+        assert!(apply(&mut g, Player(0), &s(0, (1, 1)), &mut request));
+        // 不是自己的回合，或者冒名走对方的棋
+        assert!(!apply(&mut g, Player(0), &s(0, (0, 0)), &mut request));
+        assert!(!apply(&mut g, Player(1), &s(0, (0, 0)), &mut request));
+        assert!(apply(&mut g, Player(1), &s(1, (0, 0)), &mut request));
+
+        assert!(apply(&mut g, Player(0), &Action::Ask(Request::Undo), &mut request));
+        // 自己不能答复自己，答复要对得上
+        assert!(!apply(&mut g, Player(0), &Action::Reply(Request::Undo, true), &mut request));
+        assert!(!apply(&mut g, Player(1), &Action::Reply(Request::Draw, true), &mut request));
+        assert!(apply(&mut g, Player(1), &Action::Reply(Request::Undo, true), &mut request));
+        assert!(request.is_none());
+        assert!(g.hist_steps.is_empty());
+
+        assert!(apply(&mut g, Player(1), &Action::Ask(Request::Draw), &mut request));
+        assert!(apply(&mut g, Player(0), &Action::Reply(Request::Draw, true), &mut request));
+        assert!(g.state == GameState::Over(OutCome::Draw));
+        assert!(!apply(&mut g, Player(0), &Action::Resign, &mut request));
+
+        let mut g = game();
+        assert!(apply(&mut g, Player(1), &Action::Leave("引擎已退出".to_string()), &mut request));
+        assert!(g.state == GameState::Over(OutCome::Winer(Player(0))));
+    }
+
+    #[test]
+    fn computer_answers_and_moves() {
+        let mut g = game();
+        let mut c = Computer::new(Level::FULL, None);
+        assert!(c.poll(&g, Player(1), &Input::None, None).is_none());
+        assert!(matches!(c.poll(&g, Player(1), &Input::None, Some(Request::Undo)), Some(Action::Reply(Request::Undo, true))));
+        for pos in [(0, 0), (1, 1), (0, 1)] {
+            g.step(TTTStep::new_put_step(pos, g.curr_player));
+        }
+        // 轮到1，必须堵(0,2)
+        assert!(matches!(c.poll(&g, Player(1), &Input::None, None), Some(Action::Step(s)) if s.to_pos() == (0, 2)));
+    }
+
+    /// 总是给出同一步的走法表
+    struct Fixed((u8, u8));
+
+    impl Oracle<TTTBoard, TTTStep> for Fixed {
+        fn best_step(&self, _board: &TTTBoard, next: Player) -> Option<TTTStep> {
+            Some(TTTStep::new_put_step(self.0, next))
+        }
+    }
+
+    #[test]
+    fn oracle_only_at_full_level() {
+        let mut g = game();
+        for pos in [(0, 0), (1, 1), (0, 1)] {
+            g.step(TTTStep::new_put_step(pos, g.curr_player));
+        }
+        let oracle = Fixed((2, 2));
+        let step = |level| match Computer::new(level, Some(&oracle as &dyn Oracle<_, _>)).poll(&g, Player(1), &Input::None, None) {
+            Some(Action::Step(s)) => s.to_pos(),
+            _ => panic!(),
+        };
+        assert_eq!(step(Level::FULL), (2, 2));
+        // 降低难度后自己搜索，会去堵(0,2)
+        for level in [Level::NORMAL, Level::EASY, Level { depth: 4, ..Level::FULL }] {
+            assert!(!level.is_full());
+            assert_eq!(step(level), (0, 2), "{:?}", level);
+        }
+        assert!(!Level::FULL.with_engine(ai::Engine::Mcts(crate::mcts::MctsConfig::DEFAULT)).is_full());
+    }
+}
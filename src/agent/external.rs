@@ -0,0 +1,282 @@
+// 外部引擎：启动一个程序，通过它的标准输入输出下棋
+//
+// 文本协议，所有游戏通用（一行一条，→为发给引擎，←为引擎的回复）：
+//   → NEW <局面>     新局面，格式见position.rs，此后的MOVE从这里接着走
+//   → MOVE <步子>    对方走了一步，步子见record.rs
+//   → GO <毫秒>      轮到引擎，限时思考
+//   ← MOVE <步子>    引擎的走法，引擎自己记下这一步
+//   → QUIT
+// Piskvork协议，只用于五子棋：START、INFO timeout_turn、BEGIN、TURN、BOARD/DONE、END，
+// 坐标写作`列,行`。引擎输出的其它行（MESSAGE、DEBUG等）忽略。
+// 回复超时、走法不合法或程序退出时这个座位离开对局，按认输处理。
+
+use std::{io::{BufRead, BufReader, Write}, process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError}, thread, time::{Duration, Instant}};
+
+use crate::{base::{Game, Step, Player, GameState}, position::{self, SetUp}, record};
+
+use super::{Agent, Action, Input, Request};
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Protocol {
+    Text,
+    Piskvork,
+}
+
+/// 没有棋钟时每步的思考时间
+pub const MOVE_TIME: Duration = Duration::from_secs(5);
+/// 等回复时在思考时间之外多等的时间，包括启动程序
+const GRACE: Duration = Duration::from_secs(2);
+/// 退出时等引擎自己结束的时间，过了就杀掉
+const QUIT_WAIT: Duration = Duration::from_millis(500);
+
+pub struct External {
+    command: String,
+    protocol: Protocol,
+    move_time: Duration,
+    child: Option<Child>,
+    stdin: Option<ChildStdin>,
+    lines: Option<Receiver<String>>,
+    /// 引擎知道的局面：开局局面与之后的步子
+    known: Option<(String, Vec<String>)>,
+    /// 出过错就不再启动，轮到时直接离开
+    left: Option<String>,
+}
+
+impl External {
+    /// command按shell的方式执行，可以带参数
+    pub fn new(command: &str, protocol: Protocol, move_time: Duration) -> Self {
+        External { command: command.to_string(), protocol, move_time, child: None, stdin: None, lines: None, known: None, left: None }
+    }
+
+    fn start(&mut self) -> Result<(), String> {
+        if self.child.is_some() {
+            return Ok(());
+        }
+        let mut child = Command::new("sh").arg("-c").arg(&self.command)
+            .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::null())
+            .spawn().map_err(|e| format!("启动失败：{}", e))?;
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let (tx, rx) = mpsc::channel();
+        // 程序退出时读到结尾，发送端关闭
+        thread::spawn(move || {
+            for line in stdout.lines() {
+                let Ok(line) = line else { break };
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        self.stdin = child.stdin.take();
+        self.lines = Some(rx);
+        self.child = Some(child);
+        if self.protocol == Protocol::Piskvork {
+            self.send("START 15")?;
+            self.expect(Instant::now() + GRACE, |l| (l == "OK").then_some(()))?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, line: &str) -> Result<(), String> {
+        let stdin = self.stdin.as_mut().ok_or("引擎没有启动")?;
+        writeln!(stdin, "{}", line).and_then(|_| stdin.flush()).map_err(|_| "引擎已退出".to_string())
+    }
+
+    /// 等到第一行f认得的回复，其它行跳过
+    fn expect<T>(&mut self, deadline: Instant, f: impl Fn(&str) -> Option<T>) -> Result<T, String> {
+        let lines = self.lines.as_ref().ok_or("引擎没有启动")?;
+        loop {
+            let wait = deadline.saturating_duration_since(Instant::now());
+            match lines.recv_timeout(wait) {
+                Ok(line) => if let Some(t) = f(line.trim()) {
+                    return Ok(t);
+                },
+                Err(RecvTimeoutError::Timeout) => return Err("回复超时".to_string()),
+                Err(RecvTimeoutError::Disconnected) => return Err("引擎已退出".to_string()),
+            }
+        }
+    }
+
+    fn stop(&mut self) {
+        if self.stdin.is_some() {
+            let _ = self.send(if self.protocol == Protocol::Piskvork { "END" } else { "QUIT" });
+        }
+        // 关掉输入，给引擎一点时间自己退出
+        self.stdin = None;
+        self.lines = None;
+        if let Some(mut child) = self.child.take() {
+            let deadline = Instant::now() + QUIT_WAIT;
+            while matches!(child.try_wait(), Ok(None)) && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(10));
+            }
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+
+    /// 把局面同步给引擎并要一步棋
+    fn think<B: SetUp<S>, S: Step>(&mut self, game: &Game<B, S>, me: Player) -> Result<S, String> {
+        self.start()?;
+        let time = game.clock.as_ref().map_or(self.move_time, |c| c.budget(me, Instant::now()));
+        let first = game.hist_steps.first().map_or(game.curr_player, |s| s.who());
+        let start = position::to_string(&game.start, first);
+        let steps: Vec<String> = game.hist_steps.iter().map(record::step_to_string).collect();
+        // 引擎知道的是现在的前一段时只补上后面的步子，否则（悔棋、编辑后）从头来
+        let new = match &self.known {
+            Some((s, k)) if *s == start && steps.starts_with(k) => steps[k.len()..].to_vec(),
+            _ => {
+                self.known = None;
+                steps.clone()
+            },
+        };
+
+        let ms = time.as_millis();
+        match self.protocol {
+            Protocol::Text => {
+                if self.known.is_none() {
+                    self.send(&format!("NEW {}", start))?;
+                }
+                for s in &new {
+                    self.send(&format!("MOVE {}", s))?;
+                }
+                self.send(&format!("GO {}", ms))?;
+            },
+            Protocol::Piskvork => {
+                self.send(&format!("INFO timeout_turn {}", ms))?;
+                match (self.known.is_some(), new.as_slice()) {
+                    (false, []) if game.board.cells().iter().all(|c| c.is_none()) => self.send("BEGIN")?,
+                    (true, [s]) => {
+                        let (x, y) = record::parse_pos(s).ok_or("走法")?;
+                        self.send(&format!("TURN {},{}", y, x))?;
+                    },
+                    _ => {
+                        self.send("BOARD")?;
+                        let side = game.board.side() as usize;
+                        for (i, c) in game.board.cells().iter().enumerate() {
+                            if let Some(p) = c {
+                                self.send(&format!("{},{},{}", i % side, i / side, if *p == me { 1 } else { 2 }))?;
+                            }
+                        }
+                        self.send("DONE")?;
+                    },
+                }
+            },
+        }
+
+        let deadline = Instant::now() + time + GRACE;
+        let step: S = match self.protocol {
+            Protocol::Text => self.expect(deadline, |l| record::parse_step(l.strip_prefix("MOVE ")?, me))?,
+            Protocol::Piskvork => self.expect(deadline, |l| {
+                let (x, y) = record::parse_pos(l)?;
+                Some(S::new_put_step((y, x), me))
+            })?,
+        };
+        if game.board.copy_put(step).is_none() {
+            return Err(format!("走法不合法：{}", record::step_to_string(&step)));
+        }
+        let mut steps = steps;
+        steps.push(record::step_to_string(&step));
+        self.known = Some((start, steps));
+        Ok(step)
+    }
+}
+
+impl<B: SetUp<S>, S: Step> Agent<B, S> for External {
+    fn poll(&mut self, game: &Game<B, S>, me: Player, _input: &Input, asked: Option<Request>) -> Option<Action<S>> {
+        // 引擎不懂悔棋与求和，一律拒绝
+        if let Some(r) = asked {
+            return Some(Action::Reply(r, false));
+        }
+        if game.curr_player != me || game.state != GameState::Running {
+            return None;
+        }
+        if let Some(why) = &self.left {
+            return Some(Action::Leave(why.clone()));
+        }
+        match self.think(game, me) {
+            Ok(step) => Some(Action::Step(step)),
+            Err(why) => {
+                self.stop();
+                self.left = Some(why.clone());
+                Some(Action::Leave(why))
+            },
+        }
+    }
+
+    fn name(&self) -> String {
+        let program = self.command.split_whitespace().next().unwrap_or("引擎");
+        program.rsplit('/').next().unwrap_or(program).to_string()
+    }
+}
+
+impl Drop for External {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::{base::{Board, GameType}, tic_tac_toe::{TTTBoard, TTTStep}, gobang::{GoBoard, GoStep}};
+
+    fn ttt() -> Game<TTTBoard, TTTStep> {
+        Game::new(TTTBoard::new(), GameType::Put)
+    }
+
+    fn poll<B: SetUp<S>, S: Step>(e: &mut External, game: &Game<B, S>) -> Option<Action<S>> {
+        e.poll(game, game.curr_player, &Input::None, None)
+    }
+
+    /// 把收到的命令写进文件，GO时回答固定的一步
+    fn scripted(log: &str, reply: &str) -> String {
+        format!("while read l; do echo \"$l\" >> {}; case \"$l\" in GO*) echo '# thinking'; echo '{}';; esac; done", log, reply)
+    }
+
+    #[test]
+    fn text_protocol_syncs_moves() {
+        let log = std::env::temp_dir().join(format!("rustbang-ext-{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&log);
+        let mut e = External::new(&scripted(log.to_str().unwrap(), "MOVE 1,1"), Protocol::Text, Duration::from_millis(300));
+        let mut game = ttt();
+        game.step(TTTStep::new_put_step((0, 0), Player(0)));
+        let Some(Action::Step(s)) = poll(&mut e, &game) else { panic!() };
+        assert_eq!(s.to_pos(), (1, 1));
+        game.step(s);
+        game.step(TTTStep::new_put_step((2, 2), Player(0)));
+        // 引擎再次回答1,1，已经有子了
+        assert!(matches!(poll(&mut e, &game), Some(Action::Leave(why)) if why.contains("不合法")));
+        // 离开后不再启动
+        assert!(matches!(poll(&mut e, &game), Some(Action::Leave(_))));
+        let sent = std::fs::read_to_string(&log).unwrap();
+        let _ = std::fs::remove_file(&log);
+        assert_eq!(sent.lines().collect::<Vec<_>>(), ["NEW 3/3/3 o", "MOVE 0,0", "GO 300", "MOVE 2,2", "GO 300", "QUIT"]);
+    }
+
+    #[test]
+    fn crash_and_timeout_leave() {
+        let game = ttt();
+        let mut e = External::new("exit 1", Protocol::Text, Duration::from_millis(100));
+        assert!(matches!(poll(&mut e, &game), Some(Action::Leave(_))));
+        let mut e = External::new("sleep 10", Protocol::Text, Duration::from_millis(100));
+        let t = Instant::now();
+        assert!(matches!(poll(&mut e, &game), Some(Action::Leave(why)) if why == "回复超时"));
+        assert!(t.elapsed() < Duration::from_secs(5));
+        // 拒绝请求
+        assert!(matches!(e.poll(&game, Player(1), &Input::None, Some(Request::Undo)), Some(Action::Reply(Request::Undo, false))));
+    }
+
+    #[test]
+    fn piskvork_coordinates() {
+        let script = "while read l; do case \"$l\" in START*) echo OK;; BEGIN|TURN*|DONE) echo 'MESSAGE hi'; echo '8,6';; esac; done";
+        let mut e = External::new(script, Protocol::Piskvork, Duration::from_millis(300));
+        let mut game: Game<GoBoard, GoStep> = Game::new(GoBoard::new(), GameType::Put);
+        // BEGIN：列8行6，即(6,8)
+        let Some(Action::Step(s)) = poll(&mut e, &game) else { panic!() };
+        assert_eq!(s.to_pos(), (6, 8));
+        game.step(s);
+        game.step(GoStep::new_put_step((7, 7), Player(1)));
+        // TURN之后又回答同一点，不合法
+        assert!(matches!(poll(&mut e, &game), Some(Action::Leave(_))));
+    }
+}
@@ -1,63 +1,921 @@
-use std::cmp::{max, min};
-use crate::base::{Player, Board, GameState, Step};
+mod order;
+mod score;
+
+use std::{cmp::{max, Reverse}, iter, sync::{Mutex, atomic::{AtomicI32, AtomicUsize, Ordering}}, thread, time::{Duration, Instant}};
+
+use rand::Rng;
+
+use crate::mcts::{Mcts, MctsConfig, Playout};
+use crate::base::{Player, Board, GameState, OutCome, Step, Game, GameType, DrawRule, History, Symmetry, position_key};
+use order::{Order, TTEntry, Bound, step_key};
+pub use score::Score;
 
 // struct AI {}
 
 pub trait AI<S> :Board<S> {
-    fn score(&self, player: Player) -> i16;
+    /// 局面评分，不必处理胜负的距离，搜索时会换成Score
+    fn score(&self, player: Player) -> i32;
 
     /// 生成要搜索的步子
     fn get_possible_steps(&self, player: Player, deep: u8) -> Vec<S>;
+
+    /// 双方各自的得分（己方，对方），用于调整攻守风格
+    /// 默认无法拆分，全部算作己方
+    fn side_scores(&self, player: Player) -> (i32, i32) {
+        (self.score(player), 0)
+    }
+}
+
+/// 电脑的风格
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Style {
+    Balanced,
+    /// 更看重己方的棋形
+    Aggressive,
+    /// 更看重破坏对方的棋形
+    Defensive,
+}
+
+impl Style {
+    /// 己方、对方得分的权重（百分比）
+    fn weights(&self) -> (i32, i32) {
+        match self {
+            Style::Balanced => (100, 100),
+            Style::Aggressive => (150, 75),
+            Style::Defensive => (75, 150),
+        }
+    }
+}
+
+/// 电脑使用的搜索算法
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Engine {
+    AlphaBeta,
+    /// 蒙特卡洛树搜索，不用评分函数也能下
+    Mcts(MctsConfig),
+}
+
+impl Engine {
+    pub fn from_name(name: &str) -> Option<Engine> {
+        match name {
+            "alphabeta" => Some(Engine::AlphaBeta),
+            "mcts" => Some(Engine::Mcts(MctsConfig::DEFAULT)),
+            "mcts-heuristic" => Some(Engine::Mcts(MctsConfig { playout: Playout::Heuristic, ..MctsConfig::DEFAULT })),
+            _ => None,
+        }
+    }
+}
+
+/// 电脑的难度
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Level {
+    /// 搜索深度
+    pub depth: u8,
+    /// 思考时间上限，到时返回已完成的最深一层结果
+    pub time: Option<Duration>,
+    /// 根节点每步得分加上[-noise, noise]的随机数
+    pub noise: i32,
+    /// 在与最高分相差不超过margin的步子中随机选
+    pub margin: i32,
+    pub style: Style,
+    /// 搜索线程数，1为单线程，结果可复现
+    pub threads: u8,
+    pub engine: Engine,
+}
+
+impl Level {
+    pub const BEGINNER: Level = Level { depth: 2, time: None, noise: 200, margin: 100, style: Style::Balanced, threads: 1, engine: Engine::AlphaBeta };
+    pub const EASY: Level = Level { depth: 4, time: None, noise: 50, margin: 30, style: Style::Balanced, threads: 1, engine: Engine::AlphaBeta };
+    pub const NORMAL: Level = Level { depth: 6, time: None, noise: 0, margin: 10, style: Style::Balanced, threads: 1, engine: Engine::AlphaBeta };
+    pub const FULL: Level = Level { depth: 8, time: None, noise: 0, margin: 0, style: Style::Balanced, threads: 1, engine: Engine::AlphaBeta };
+
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "beginner" => Some(Self::BEGINNER),
+            "easy" => Some(Self::EASY),
+            "normal" => Some(Self::NORMAL),
+            "full" | "hard" => Some(Self::FULL),
+            _ => None,
+        }
+    }
+
+    pub fn with_style(self, style: Style) -> Level {
+        Level { style, ..self }
+    }
+
+    pub fn with_threads(self, threads: u8) -> Level {
+        Level { threads: threads.max(1), ..self }
+    }
+
+    pub fn with_engine(self, engine: Engine) -> Level {
+        Level { engine, ..self }
+    }
+
+    /// 全力模式才使用完美走法表，指定蒙特卡洛时也不用
+    pub fn is_full(&self) -> bool {
+        self.depth >= Self::FULL.depth && self.noise == 0 && self.margin == 0 && self.engine == Engine::AlphaBeta
+    }
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Self::FULL
+    }
+}
+
+/// 给棋盘套上风格，改变评分方式
+#[derive(Clone)]
+struct Styled<B> {
+    board: B,
+    style: Style,
+}
+
+impl<B: Board<S>, S: Step> Board<S> for Styled<B> {
+    fn new() -> Self {
+        Styled { board: B::new(), style: Style::Balanced }
+    }
+
+    fn put(&mut self, step: S) -> bool {
+        self.board.put(step)
+    }
+
+    fn over(&self) -> GameState {
+        self.board.over()
+    }
+
+    fn key(&self) -> u64 {
+        self.board.key()
+    }
+
+    fn pieces_count(&self) -> u16 {
+        self.board.pieces_count()
+    }
+}
+
+impl<B: AI<S>, S: Step> AI<S> for Styled<B> {
+    fn score(&self, player: Player) -> i32 {
+        if self.style == Style::Balanced || self.board.over() != GameState::Running {
+            return self.board.score(player);
+        }
+        let (me, ot) = self.board.side_scores(player);
+        let (wm, wo) = self.style.weights();
+        (me * wm - ot * wo) / 100
+    }
+
+    fn get_possible_steps(&self, player: Player, deep: u8) -> Vec<S> {
+        self.board.get_possible_steps(player, deep)
+    }
+}
+
+/// 走棋类游戏搜索时带上的局面记录，用来识别重复局面与无吃子和棋
+#[derive(Clone)]
+pub struct DrawCheck {
+    pub rule: DrawRule,
+    pub history: History,
+}
+
+/// 一次搜索的上下文
+pub struct SearchCtx {
+    pub draw: Option<DrawCheck>,
+    pub deadline: Option<Instant>,
+    /// 超时后置为true，搜索结果作废
+    pub aborted: bool,
+    /// 搜索过的节点数
+    pub nodes: u64,
+    /// 根节点的行棋方，评分以它为准
+    pub root: Player,
+    /// 当前节点距根节点的步数
+    ply: usize,
+    order: Order,
+    /// 多线程搜索时其余线程的上下文，各有各的置换表，跨层保留
+    workers: Vec<SearchCtx>,
+}
+
+impl SearchCtx {
+    pub fn new(draw: Option<DrawCheck>) -> Self {
+        SearchCtx { draw, deadline: None, aborted: false, nodes: 0, root: Player(0), ply: 0, order: Order::new(), workers: vec![] }
+    }
+
+    /// 设置搜索线程数，包括当前线程
+    pub fn set_threads(&mut self, threads: u8) {
+        let n = threads.max(1) as usize - 1;
+        self.workers.truncate(n);
+        while self.workers.len() < n {
+            self.workers.push(SearchCtx::new(None));
+        }
+    }
+
+    /// 从主线程同步一次根节点搜索需要的状态
+    fn sync_from(&mut self, main: &SearchCtx) {
+        self.draw = main.draw.clone();
+        self.deadline = main.deadline;
+        self.aborted = false;
+        self.nodes = 0;
+        self.root = main.root;
+        self.ply = 0;
+    }
+
+    fn check_time(&mut self) -> bool {
+        if let Some(d) = self.deadline {
+            if Instant::now() >= d {
+                self.aborted = true;
+            }
+        }
+        self.aborted
+    }
+}
+
+/// 根节点的一步
+#[derive(Clone, Debug)]
+pub struct RootMove<S> {
+    pub step: S,
+    pub score: Score,
+    /// 预期的后续走法（主要变例），第一步就是step
+    pub pv: Vec<S>,
+}
+
+/// 搜索结果
+#[derive(Clone, Debug)]
+pub struct Analysis<S> {
+    /// 完成的搜索深度
+    pub depth: u8,
+    pub nodes: u64,
+    /// 按得分从高到低
+    pub moves: Vec<RootMove<S>>,
+}
+
+const INF: Score = Score::INF;
+/// 渴望窗口的半宽
+const ASPIRATION: i32 = 64;
+
+/// 局面评分，始终以根节点行棋方为准再换算到player，风格不对称时对手不会被当成同样风格
+/// 分出胜负时按距根节点的步数给分，赢得越快、输得越慢越好
+fn evaluate<B: AI<S>,S: Step>(board: &B, player: Player, ctx: &SearchCtx) -> Score {
+    match board.over() {
+        GameState::Over(OutCome::Winer(p)) if p == player => Score::win_in(ctx.ply),
+        GameState::Over(OutCome::Timeout(p)) if p != player => Score::win_in(ctx.ply),
+        GameState::Over(OutCome::Winer(_) | OutCome::Timeout(_)) => Score::loss_in(ctx.ply),
+        GameState::Over(OutCome::Draw) => Score::DRAW,
+        GameState::Running => {
+            let s = Score::eval(board.score(ctx.root));
+            if player == ctx.root { s } else { -s }
+        },
+    }
+}
+
+/// 负极大值搜索（主要变例搜索），返回对行棋方player而言的得分，depth为剩余深度
+/// pv返回预期走法
+pub fn negamax<B: AI<S>,S: Step>(board: &B, player: Player, depth: u8,
+    mut alpha: Score, beta: Score, ctx: &mut SearchCtx, pv: &mut Vec<S>) -> Score {
+
+    ctx.nodes += 1;
+    if depth == 0 || board.over() != GameState::Running || ctx.check_time() {
+        return evaluate(board, player, ctx);
+    }
+
+    let mut steps = board.get_possible_steps(player, depth);
+    if steps.is_empty() {
+        return evaluate(board, player, ctx);
+    }
+
+    // 置换表：零窗口节点可以直接用表中的分数；走棋类游戏的和棋与路径有关，不能直接用
+    let key = position_key(board, player);
+    let entry = ctx.order.probe(key);
+    if let Some(e) = entry {
+        let score = e.score.to_root(ctx.ply);
+        if ctx.draw.is_none() && beta.0 as i64 - alpha.0 as i64 == 1 && e.depth >= depth {
+            match e.bound {
+                Bound::Exact => return score,
+                Bound::Lower if score >= beta => return score,
+                Bound::Upper if score <= alpha => return score,
+                _ => (),
+            }
+        }
+    }
+    ctx.order.sort(&mut steps, player, ctx.ply, entry.and_then(|e| e.best));
+
+    let alpha_orig = alpha;
+    let mut best = -INF;
+    let mut best_step = None;
+    for (i, s) in steps.into_iter().enumerate() {
+        let mut child_pv = vec![];
+        let score = if i == 0 {
+            -search_child(board, s, depth, -beta, -alpha, ctx, &mut child_pv)
+        } else {
+            // 先用零窗口验证，落在窗口内再完整搜索
+            let mut score = -search_child(board, s, depth, -alpha - 1, -alpha, ctx, &mut child_pv);
+            if score > alpha && score < beta {
+                child_pv.clear();
+                score = -search_child(board, s, depth, -beta, -alpha, ctx, &mut child_pv);
+            }
+            score
+        };
+
+        if score > best {
+            best = score;
+            best_step = Some(step_key(&s));
+            pv.clear();
+            pv.push(s);
+            pv.append(&mut child_pv);
+        }
+        alpha = max(alpha, score);
+        if alpha >= beta {
+            ctx.order.cutoff(&s, player, ctx.ply, depth);
+            break;
+        }
+    }
+
+    if !ctx.aborted {
+        let bound = if best <= alpha_orig {
+            Bound::Upper
+        } else if best >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        ctx.order.store(key, TTEntry { depth, score: best.to_node(ctx.ply), bound, best: best_step });
+    }
+    best
 }
 
-/// player指对谁来评分
-pub fn get_score<B: AI<S>,S: Step>(board: &B, step: S, me: Player,
-    deep: u8, alpha: i16, beta: i16) -> i16 {
+/// 走step后搜索子节点，返回对子节点行棋方而言的得分
+/// 走棋类游戏在这里判断重复局面与无吃子和棋
+fn search_child<B: AI<S>,S: Step>(board: &B, step: S, depth: u8,
+    alpha: Score, beta: Score, ctx: &mut SearchCtx, pv: &mut Vec<S>) -> Score {
 
-    let player = step.who();
+    let next = step.who().rev();
     let new_board = board.copy_put(step).unwrap();
 
-    if deep == 1 || new_board.over() != GameState::Running {
-        return new_board.score(me);
+    // 和棋局面直接记0分，落后时会主动找和，领先时会避开
+    let mut saved_no_eat = None;
+    if let Some(d) = ctx.draw.as_mut() {
+        let eat = new_board.pieces_count() < board.pieces_count();
+        saved_no_eat = Some(d.history.no_eat);
+        d.history.push(position_key(&new_board, next), eat);
+        if new_board.over() == GameState::Running && d.history.is_draw(&d.rule) {
+            d.history.keys.pop();
+            d.history.no_eat = saved_no_eat.unwrap();
+            return Score::DRAW;
+        }
+    }
+
+    ctx.ply += 1;
+    let score = negamax(&new_board, next, depth - 1, alpha, beta, ctx, pv);
+    ctx.ply -= 1;
+
+    if let Some(d) = ctx.draw.as_mut() {
+        d.history.keys.pop();
+        d.history.no_eat = saved_no_eat.unwrap();
+    }
+    score
+}
+
+/// 对局的搜索上下文，走棋类游戏会考虑重复局面与无吃子和棋
+pub fn game_ctx<B: AI<S>,S: Step>(game: &Game<B, S>) -> SearchCtx {
+    let draw = match game.game_type {
+        GameType::Move => Some(DrawCheck { rule: game.draw_rule, history: game.history.clone() }),
+        GameType::Put => None,
+    };
+    SearchCtx::new(draw)
+}
+
+/// 有棋钟时，思考时间不超过棋钟给这一步的预算
+pub fn timed_level<B: Board<S>,S: Step>(game: &Game<B, S>, level: Level) -> Level {
+    match &game.clock {
+        Some(c) => {
+            let budget = c.budget(game.curr_player, Instant::now());
+            Level { time: Some(level.time.map_or(budget, |t| t.min(budget))), ..level }
+        },
+        None => level,
     }
+}
 
-    let mut max_score = i16::MIN;
-    let mut min_score = i16::MAX;
-    let steps = new_board.get_possible_steps(player.rev(), deep-1);
-    if steps.len() == 0 {
-        return new_board.score(me);
+/// 对局中使用，按level搜索，有棋钟时不超过预算。蒙特卡洛用tree搜索，走棋之间保留子树
+pub fn get_game_best_step<B: AI<S>,S: Step>(game: &Game<B, S>, level: &Level, tree: &mut Mcts<B, S>) -> Option<S> {
+    let level = timed_level(game, *level);
+    if let Engine::Mcts(config) = level.engine {
+        return tree.game_best_step(game, &level, &config, &mut rand::thread_rng());
     }
+    search_best_step(&game.board, game.curr_player, &level, game_ctx(game))
+}
+
+pub fn search_best_step<B: AI<S>,S: Step>(board: &B, player: Player, level: &Level,
+    ctx: SearchCtx) -> Option<S> {
+
+    // 要在接近最高分的步子中选时，需要每一步的准确得分
+    let exact = level.noise > 0 || level.margin > 0;
+    let analysis = search_styled(board, player, level, ctx, exact);
+    choose_step(analysis.moves, level, &mut rand::thread_rng())
+}
+
+/// 搜索并返回根节点所有步子的准确得分与主要变例
+pub fn analyze<B: AI<S>,S: Step>(board: &B, player: Player, level: &Level,
+    ctx: SearchCtx) -> Analysis<S> {
+
+    search_styled(board, player, level, ctx, true)
+}
+
+fn search_styled<B: AI<S>,S: Step>(board: &B, player: Player, level: &Level,
+    ctx: SearchCtx, exact: bool) -> Analysis<S> {
+
+    if level.style != Style::Balanced {
+        let styled = Styled { board: board.clone(), style: level.style };
+        return search_leveled(&styled, player, level, ctx, exact);
+    }
+    search_leveled(board, player, level, ctx, exact)
+}
+
+/// exact为false时，只有最好一步的得分是准确的，其余为上界
+fn search_leveled<B: AI<S>,S: Step>(board: &B, player: Player, level: &Level,
+    mut ctx: SearchCtx, exact: bool) -> Analysis<S> {
 
-    for s in steps {
-        let score = get_score(&new_board, s, me, deep-1, max_score, min_score);
-        max_score = max(max_score, score);
-        min_score = min(min_score, score);
+    // 逐层加深，浅层的结果给深层排序；有时间限制时超时则用上一层的结果
+    ctx.deadline = level.time.map(|t| Instant::now() + t);
+    ctx.set_threads(level.threads);
+    let depths: Vec<u8> = match level.time {
+        Some(_) => (2..=level.depth).collect(),
+        None => vec![level.depth],
+    };
 
-        // alpha，beta剪枝
-        if player == me && score < alpha {
+    let mut analysis = Analysis { depth: 0, nodes: 0, moves: vec![] };
+    for depth in depths {
+        let prev = analysis.moves.first().map(|m| (m.score, step_key(&m.step)));
+        let v = search_root(board, player, depth, &mut ctx, exact, prev);
+        if ctx.aborted && !analysis.moves.is_empty() {
             break;
-        } else if player != me && score > beta {
+        }
+        analysis.moves = v;
+        analysis.depth = depth;
+        if ctx.aborted {
             break;
         }
     }
-    
-    if player == me {
-        min_score
-    } else {
-        max_score
+    analysis.nodes = ctx.nodes;
+    // 稳定排序，同分时保持生成顺序
+    analysis.moves.sort_by_key(|m| Reverse(m.score));
+    analysis
+}
+
+/// 根节点每一步的得分，按搜索顺序
+/// prev为上一层最好的一步及得分：先搜这一步，并用得分设置渴望窗口，失败时用完整窗口重搜
+fn search_root<B: AI<S>,S: Step>(board: &B, player: Player, depth: u8,
+    ctx: &mut SearchCtx, exact: bool, prev: Option<(Score, order::StepKey)>) -> Vec<RootMove<S>> {
+
+    ctx.root = player;
+    ctx.ply = 0;
+    // 根节点不按深度收窄候选
+    let mut steps = board.get_possible_steps(player, u8::MAX);
+    if let Some((_, key)) = prev {
+        if let Some(i) = steps.iter().position(|s| step_key(s) == key) {
+            let s = steps.remove(i);
+            steps.insert(0, s);
+        }
+    }
+    let guess = prev.map(|p| p.0);
+
+    if !ctx.workers.is_empty() {
+        return search_root_parallel(board, &steps, depth, ctx, exact, guess);
+    }
+
+    if exact {
+        return steps.into_iter().map(|s| {
+            let mut rest = vec![];
+            let score = -search_child(board, s, depth, -INF, INF, ctx, &mut rest);
+            root_move(s, score, rest)
+        }).collect();
+    }
+
+    let (alpha, beta) = match guess {
+        Some(g) => (g - ASPIRATION, g + ASPIRATION),
+        None => (-INF, INF),
+    };
+    let v = search_root_window(board, &steps, depth, alpha, beta, ctx);
+    let best = v.iter().map(|m| m.score).max().unwrap_or(Score::DRAW);
+    if !ctx.aborted && ((best <= alpha && alpha > -INF) || (best >= beta && beta < INF)) {
+        return search_root_window(board, &steps, depth, -INF, INF, ctx);
+    }
+    v
+}
+
+fn search_root_window<B: AI<S>,S: Step>(board: &B, steps: &[S], depth: u8,
+    mut alpha: Score, beta: Score, ctx: &mut SearchCtx) -> Vec<RootMove<S>> {
+
+    let mut v = vec![];
+    for (i, s) in steps.iter().enumerate() {
+        let mut rest = vec![];
+        let score = if i == 0 {
+            -search_child(board, *s, depth, -beta, -alpha, ctx, &mut rest)
+        } else {
+            let mut score = -search_child(board, *s, depth, -alpha - 1, -alpha, ctx, &mut rest);
+            if score > alpha && score < beta {
+                rest.clear();
+                score = -search_child(board, *s, depth, -beta, -alpha, ctx, &mut rest);
+            }
+            score
+        };
+        alpha = max(alpha, score);
+        v.push(root_move(*s, score, rest));
+    }
+    v
+}
+
+/// 多线程搜索根节点：主线程先搜第一步定下窗口，其余步子各线程轮流领取
+/// 线程间共享当前最好得分用来剪枝，置换表各自独立
+fn search_root_parallel<B: AI<S>,S: Step>(board: &B, steps: &[S], depth: u8,
+    ctx: &mut SearchCtx, exact: bool, guess: Option<Score>) -> Vec<RootMove<S>> {
+
+    let (alpha, beta) = match guess {
+        Some(g) if !exact => (g - ASPIRATION, g + ASPIRATION),
+        _ => (-INF, INF),
+    };
+    let v = search_root_split(board, steps, depth, alpha, beta, ctx, exact);
+    let best = v.iter().map(|m| m.score).max().unwrap_or(Score::DRAW);
+    if !ctx.aborted && ((best <= alpha && alpha > -INF) || (best >= beta && beta < INF)) {
+        return search_root_split(board, steps, depth, -INF, INF, ctx, exact);
     }
+    v
 }
 
-pub fn get_next_best_step<B: AI<S>,S: Step>(board: &B, player: Player) -> Option<S> {
-    let mut best = None;
-    let mut score = i16::MIN;
-    for s in board.get_possible_steps(player, 10) {
-        let ns = get_score(board, s, player, 8, i16::MIN, i16::MAX);
-        // println!("step: {:?}: {}", s, ns);
-        if score < ns {
-            score = ns;
-            best = Some(s);
+fn search_root_split<B: AI<S>,S: Step>(board: &B, steps: &[S], depth: u8,
+    alpha: Score, beta: Score, ctx: &mut SearchCtx, exact: bool) -> Vec<RootMove<S>> {
+
+    let (first, rest) = match steps.split_first() {
+        Some(x) => x,
+        None => return vec![],
+    };
+    let mut pv = vec![];
+    let score = -search_child(board, *first, depth, -beta, -alpha, ctx, &mut pv);
+    let mut v = vec![root_move(*first, score, pv)];
+
+    let mut workers = std::mem::take(&mut ctx.workers);
+    for w in workers.iter_mut() {
+        w.sync_from(ctx);
+    }
+    let shared = AtomicI32::new(max(alpha, score).0);
+    let next = AtomicUsize::new(0);
+    let found = Mutex::new(vec![]);
+    let (shared, next, found) = (&shared, &next, &found);
+    thread::scope(|sc| {
+        for w in iter::once(&mut *ctx).chain(workers.iter_mut()) {
+            sc.spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                if i >= rest.len() || w.aborted {
+                    break;
+                }
+                let s = rest[i];
+                let mut pv = vec![];
+                // 零窗口没有超过a时得分只是上界
+                let (score, bound) = if exact {
+                    (-search_child(board, s, depth, -INF, INF, w, &mut pv), false)
+                } else {
+                    let a = Score(shared.load(Ordering::Relaxed));
+                    let mut score = -search_child(board, s, depth, -a - 1, -a, w, &mut pv);
+                    if score > a && score < beta {
+                        pv.clear();
+                        score = -search_child(board, s, depth, -beta, -a, w, &mut pv);
+                    }
+                    (score, score <= a)
+                };
+                shared.fetch_max(score.0, Ordering::Relaxed);
+                found.lock().unwrap().push((i, root_move(s, score, pv), bound));
+            });
         }
+    });
+
+    for w in workers.iter() {
+        ctx.nodes += w.nodes;
+        ctx.aborted |= w.aborted;
+    }
+    ctx.workers = workers;
+
+    let mut found = found.lock().unwrap().drain(..).collect::<Vec<_>>();
+    let best = found.iter().map(|f| f.1.score).fold(score, max);
+    resolve_ties(board, depth, best, &mut found, ctx);
+
+    // 按搜索顺序返回，与单线程一致
+    found.sort_by_key(|f| f.0);
+    v.extend(found.into_iter().map(|f| f.1));
+    v
+}
+
+/// found为（搜索顺序，结果，得分是否只是上界）。a来自其它线程时可能是排在后面的步子的得分，
+/// 上界恰好等于最高分的步子会排到真正最好的一步前面，用完整窗口重搜得到准确得分
+fn resolve_ties<B: AI<S>,S: Step>(board: &B, depth: u8, best: Score,
+    found: &mut [(usize, RootMove<S>, bool)], ctx: &mut SearchCtx) {
+
+    for (_, m, bound) in found.iter_mut() {
+        if *bound && m.score >= best && !ctx.aborted {
+            let mut pv = vec![];
+            let score = -search_child(board, m.step, depth, -INF, INF, ctx, &mut pv);
+            *m = root_move(m.step, score, pv);
+            *bound = false;
+        }
+    }
+}
+
+fn root_move<S: Step>(step: S, score: Score, mut rest: Vec<S>) -> RootMove<S> {
+    let mut pv = vec![step];
+    pv.append(&mut rest);
+    RootMove { step, score, pv }
+}
+
+/// 轮到的一方的对手下一步就能获胜的走法。落子类查所有空格，候选点可能漏掉；走棋类的候选就是全部合法走法
+pub fn threats<B: AI<S> + Symmetry<S>,S: Step>(game: &Game<B, S>) -> Vec<S> {
+    let board = &game.board;
+    let ot = game.curr_player.rev();
+    let steps = match game.game_type {
+        GameType::Put => {
+            let side = board.side() as usize;
+            board.cells().iter().enumerate().filter(|(_, c)| c.is_none())
+                .map(|(i, _)| S::new_put_step(((i / side) as u8, (i % side) as u8), ot))
+                .collect()
+        },
+        GameType::Move => board.get_possible_steps(ot, u8::MAX),
+    };
+    steps.into_iter().filter(|s| {
+        match board.copy_put(*s).map(|b| b.over()) {
+            Some(GameState::Over(OutCome::Winer(p))) => p == ot,
+            _ => false,
+        }
+    }).collect()
+}
+
+/// 加噪声后，在接近最高分的步子中随机选
+fn choose_step<S: Step, R: Rng>(moves: Vec<RootMove<S>>, level: &Level, rng: &mut R) -> Option<S> {
+    let scores: Vec<(S, i64)> = moves.into_iter().map(|RootMove { step: s, score, .. }| {
+        let noise = if level.noise > 0 { rng.gen_range(-level.noise..=level.noise) } else { 0 };
+        (s, score.0 as i64 + noise as i64)
+    }).collect();
+
+    let best = scores.iter().map(|s| s.1).max()?;
+    let near: Vec<S> = scores.iter()
+        .filter(|s| s.1 >= best - level.margin as i64)
+        .map(|s| s.0)
+        .collect();
+    if level.margin == 0 {
+        return near.first().copied();
+    }
+    Some(near[rng.gen_range(0..near.len())])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use rand::{SeedableRng, rngs::StdRng};
+    use crate::{tic_tac_toe::{TTTBoard, TTTStep}, gobang::{GoBoard, GoStep}, goT::GoTBoard, record};
+
+    /// 不剪枝的极小极大搜索，作为对照
+    fn minimax<B: AI<S>, S: Step>(board: &B, player: Player, depth: u8, root: Player, ply: usize) -> Score {
+        let eval = || match board.over() {
+            GameState::Over(OutCome::Winer(p)) if p == player => Score::win_in(ply),
+            GameState::Over(OutCome::Timeout(p)) if p != player => Score::win_in(ply),
+            GameState::Over(OutCome::Winer(_) | OutCome::Timeout(_)) => Score::loss_in(ply),
+            GameState::Over(OutCome::Draw) => Score::DRAW,
+            GameState::Running => {
+                let s = Score::eval(board.score(root));
+                if player == root { s } else { -s }
+            },
+        };
+        if depth == 0 || board.over() != GameState::Running {
+            return eval();
+        }
+        let steps = board.get_possible_steps(player, depth);
+        if steps.is_empty() {
+            return eval();
+        }
+        steps.into_iter().map(|s| {
+            -minimax(&board.copy_put(s).unwrap(), player.rev(), depth - 1, root, ply + 1)
+        }).max().unwrap()
+    }
+
+    fn minimax_root<B: AI<S>, S: Step>(board: &B, player: Player, depth: u8) -> Score {
+        board.get_possible_steps(player, u8::MAX).into_iter().map(|s| {
+            -minimax(&board.copy_put(s).unwrap(), player.rev(), depth - 1, player, 1)
+        }).max().unwrap()
+    }
+
+    /// 三种根节点搜索方式的最好得分
+    fn search_scores<B: AI<S>, S: Step>(board: &B, player: Player, depth: u8) -> [Score; 3] {
+        let best = |v: Vec<RootMove<S>>| v.iter().map(|m| m.score).max().unwrap();
+        let mut ctx = SearchCtx::new(None);
+        let exact = best(search_root(board, player, depth, &mut ctx, true, None));
+        let pvs = best(search_root(board, player, depth, &mut ctx, false, None));
+        // 故意给一个偏离很远的猜测，测试窗口失败后重搜
+        let first = step_key(&board.get_possible_steps(player, u8::MAX)[0]);
+        let asp = best(search_root(board, player, depth, &mut ctx, false, Some((pvs + 1000, first))));
+        [exact, pvs, asp]
+    }
+
+    fn board_from<B: AI<S>, S: Step>(line: &str) -> (B, Player) {
+        let start = B::new();
+        let steps = record::parse_game::<B, S>(line, &start).unwrap();
+        let mut board = start;
+        for s in &steps {
+            board.put(*s);
+        }
+        (board, Player((steps.len() % 2) as u8))
+    }
+
+    #[test]
+    fn negamax_matches_minimax_on_ttt() {
+        for line in ["", "1,1", "0,0 1,1", "0,0 1,1 2,2", "0,0 0,1 1,1", "1,1 0,0 2,2 0,2"] {
+            let (board, player) = board_from::<TTTBoard, TTTStep>(line);
+            let depth = 9 - line.split_whitespace().count() as u8;
+            let expect = minimax_root(&board, player, depth);
+            assert_eq!(search_scores(&board, player, depth), [expect; 3], "position: {:?}", line);
+        }
+    }
+
+    #[test]
+    fn negamax_matches_minimax_on_gobang() {
+        for line in ["7,7 7,8", "7,7 8,8 7,8 6,6", "7,7 7,8 8,8 6,6 9,9"] {
+            let (board, player) = board_from::<GoBoard, GoStep>(line);
+            for depth in 1..=3 {
+                let expect = minimax_root(&board, player, depth);
+                assert_eq!(search_scores(&board, player, depth), [expect; 3],
+                    "position: {:?}, depth: {}", line, depth);
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_matches_single_thread() {
+        for line in ["7,7 7,8", "7,7 8,8 7,8 6,6", "7,7 7,8 8,8 6,6 9,9"] {
+            let (board, player) = board_from::<GoBoard, GoStep>(line);
+            let level = Level { depth: 3, ..Level::FULL };
+            let scores = |threads| {
+                let a = analyze::<GoBoard, GoStep>(&board, player, &level.with_threads(threads), SearchCtx::new(None));
+                a.moves.iter().map(|m| (step_key(&m.step), m.score)).collect::<Vec<_>>()
+            };
+            assert_eq!(scores(4), scores(1), "position: {:?}", line);
+
+            let expect = minimax_root(&board, player, 3);
+            let mut ctx = SearchCtx::new(None);
+            ctx.set_threads(4);
+            let best = search_root::<GoBoard, GoStep>(&board, player, 3, &mut ctx, false, None)
+                .iter().map(|m| m.score).max().unwrap();
+            assert_eq!(best, expect, "position: {:?}", line);
+        }
+    }
+
+    fn root_moves(scores: &[i32]) -> Vec<RootMove<TTTStep>> {
+        scores.iter().enumerate().map(|(i, s)| {
+            let step = TTTStep::new_put_step(((i / 3) as u8, (i % 3) as u8), Player(0));
+            RootMove { step, score: Score::eval(*s), pv: vec![step] }
+        }).collect()
+    }
+
+    #[test]
+    fn lower_levels_pick_near_best() {
+        let scores = [100, 90, 60, -300, -1000];
+        let mut rng = StdRng::seed_from_u64(3);
+        // 能选中的步子：得分不低于最高分减去margin与两倍noise
+        for (level, allowed) in [(Level::EASY, 3), (Level::BEGINNER, 4)] {
+            let mut picked = HashSet::new();
+            for _ in 0..200 {
+                let s = choose_step(root_moves(&scores), &level, &mut rng).unwrap();
+                let i = s.to_pos().0 as usize * 3 + s.to_pos().1 as usize;
+                assert!(i < allowed, "{:?} picked {}", level, i);
+                picked.insert(i);
+            }
+            assert!(picked.len() > 1, "{:?}", level);
+        }
+        // 全力时总是最高分中的第一步
+        for _ in 0..20 {
+            let s = choose_step(root_moves(&[50, 100, 100]), &Level::FULL, &mut rng).unwrap();
+            assert_eq!(s.to_pos(), (0, 1));
+        }
+        assert!(choose_step(root_moves(&[]), &Level::EASY, &mut rng).is_none());
+    }
+
+    #[test]
+    fn style_changes_order() {
+        // 攻守的取舍不同，排在前面的步子跟着变
+        for (line, style) in [("7,7 6,7 7,8 6,8", Style::Aggressive), ("7,7 6,6 8,8 9,9", Style::Defensive)] {
+            let (board, player) = board_from::<GoBoard, GoStep>(line);
+            let order = |style| {
+                let level = Level { depth: 2, ..Level::FULL }.with_style(style);
+                let a = analyze::<GoBoard, GoStep>(&board, player, &level, SearchCtx::new(None));
+                a.moves.iter().map(|m| step_key(&m.step)).collect::<Vec<_>>()
+            };
+            assert_ne!(order(style), order(Style::Balanced), "{}", line);
+        }
+
+        // 只改变未分胜负时的评分
+        let (board, player) = board_from::<GoBoard, GoStep>("7,7 6,7 7,8 6,8");
+        let styled = Styled { board: board.clone(), style: Style::Aggressive };
+        let (me, ot) = board.side_scores(player);
+        assert_eq!(styled.score(player), (me * 150 - ot * 75) / 100);
+        let (won, _) = board_from::<TTTBoard, TTTStep>("0,0 1,0 0,1 1,1 0,2");
+        assert_eq!(Styled { board: won.clone(), style: Style::Defensive }.score(Player(0)), won.score(Player(0)));
+    }
+
+    /// 主要变例从这一步开始，双方轮流，每步都合法，返回走完后的棋盘
+    fn play_pv<B: AI<S>, S: Step>(board: &B, player: Player, m: &RootMove<S>) -> B {
+        assert_eq!(step_key(&m.pv[0]), step_key(&m.step));
+        let mut b = board.clone();
+        for (i, s) in m.pv.iter().enumerate() {
+            assert_eq!(s.who(), if i % 2 == 0 { player } else { player.rev() });
+            b = b.copy_put(*s).expect("illegal pv step");
+        }
+        b
+    }
+
+    #[test]
+    fn pv_reaches_mate() {
+        // 0走(2,0)同时堵住对方并形成双三，三步内获胜
+        let (board, player) = board_from::<TTTBoard, TTTStep>("0,0 1,1 2,2 0,2");
+        let a = analyze(&board, player, &Level { depth: 9, ..Level::FULL }, SearchCtx::new(None));
+        assert!(a.moves.windows(2).all(|w| w[0].score >= w[1].score));
+        let best = &a.moves[0];
+        assert_eq!(best.step.to_pos(), (2, 0));
+        assert_eq!(best.score, Score::win_in(3));
+        assert_eq!(best.pv.len(), 3);
+        assert!(play_pv(&board, player, best).over() == GameState::Over(OutCome::Winer(player)));
+        for m in &a.moves {
+            let b = play_pv(&board, player, m);
+            // 分出胜负的变例正好走到终局
+            if let Some(d) = m.score.mate_distance() {
+                assert_eq!(m.pv.len(), d.unsigned_abs() as usize, "{:?}", m.step);
+                assert!(b.over() != GameState::Running);
+            }
+        }
+
+        // 多线程与带深度限制的搜索也给出合法的变例
+        let (board, player) = board_from::<GoBoard, GoStep>("7,7 7,8 8,8 6,6");
+        let a = analyze(&board, player, &Level { depth: 3, ..Level::FULL }.with_threads(2), SearchCtx::new(None));
+        for m in &a.moves {
+            play_pv(&board, player, m);
+            assert!(m.pv.len() <= 3);
+        }
+    }
+
+    #[test]
+    fn threats_of_opponent() {
+        let positions = |v: Vec<TTTStep>| v.iter().map(record::step_to_string).collect::<Vec<_>>();
+        let game = |line: &str| {
+            let (board, player) = board_from::<TTTBoard, TTTStep>(line);
+            Game::new(board, GameType::Put).with_next_player(player)
+        };
+        assert_eq!(positions(threats(&game("0,0 1,1 0,1"))), ["0,2"]);
+        assert!(threats(&game("0,0 1,1")).is_empty());
+
+        // 活四两头都是
+        let (board, player) = board_from::<GoBoard, GoStep>("7,3 0,0 7,4 0,2 7,5 0,4 7,6");
+        let game = Game::new(board, GameType::Put).with_next_player(player);
+        let mut v: Vec<_> = threats(&game).iter().map(|s| s.to_pos()).collect();
+        v.sort();
+        assert_eq!(v, [(7, 2), (7, 7)]);
+
+        // 走棋类：吃子后对方只剩一子
+        let (board, _) = crate::position::parse("o.../.o../x.../...x", &GoTBoard::new()).unwrap();
+        let game = Game::new(board, GameType::Move).with_next_player(Player(1));
+        assert_eq!(threats(&game).iter().map(record::step_to_string).collect::<Vec<_>>(), ["1,1-1,0"]);
+    }
+
+    #[test]
+    fn parallel_chooses_like_single_thread() {
+        // 上界与最高分相同的步子要重搜，否则按搜索顺序排在真正最好的一步前面
+        let (board, player) = board_from::<TTTBoard, TTTStep>("0,0 1,0 0,1 1,1");
+        let put = |pos| TTTStep::new_put_step(pos, player);
+        let mut found = vec![
+            (0, root_move(put((2, 2)), Score::win_in(1), vec![]), true),
+            (1, root_move(put((0, 2)), Score::win_in(1), vec![]), false),
+        ];
+        let mut ctx = SearchCtx::new(None);
+        ctx.root = player;
+        resolve_ties(&board, 9, Score::win_in(1), &mut found, &mut ctx);
+        assert_eq!(found[0].1.score, Score::loss_in(2));
+        assert!(!found[0].2);
+        let moves = found.into_iter().map(|f| f.1).collect();
+        assert_eq!(choose_step(moves, &Level::FULL, &mut rand::thread_rng()).map(|s| s.to_pos()), Some((0, 2)));
+
+        // 不要求准确得分时只有最好一步可信，选出的步子要一致；对称的局面有很多同分的步子
+        for line in ["7,7", "7,7 7,8", "7,7 8,8", "7,7 8,8 7,8 6,6", "7,7 7,8 8,8 6,6 9,9", "7,7 6,6 8,8 9,9"] {
+            let (board, player) = board_from::<GoBoard, GoStep>(line);
+            for depth in 2..=3 {
+                let level = Level { depth, ..Level::FULL };
+                let choose = |threads| search_best_step::<GoBoard, GoStep>(&board, player, &level.with_threads(threads), SearchCtx::new(None))
+                    .map(|s| s.to_pos());
+                let expect = choose(1);
+                for _ in 0..3 {
+                    assert_eq!(choose(4), expect, "position: {:?}, depth: {}", line, depth);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn prefers_fastest_win() {
+        // 0走(0,2)立即获胜
+        let (board, player) = board_from::<TTTBoard, TTTStep>("0,0 1,0 0,1 1,1");
+        let a = analyze(&board, player, &Level { depth: 9, ..Level::FULL }, SearchCtx::new(None));
+        assert_eq!(a.moves[0].step.to_pos(), (0, 2));
+        assert_eq!(a.moves[0].score, Score::win_in(1));
+        assert_eq!(a.moves[0].score.mate_distance(), Some(1));
+        // 不走(0,2)时对方(1,2)立即获胜，得分是两步后负
+        let worst = a.moves.last().unwrap();
+        assert_eq!(worst.score, Score::loss_in(2));
     }
-    best
 }
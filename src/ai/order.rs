@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+use crate::base::{Player, Step};
+
+use super::Score;
+
+/// 步子的标识：起点（落子类为None）与终点
+pub type StepKey = (Option<(u8, u8)>, (u8, u8));
+
+pub fn step_key<S: Step>(step: &S) -> StepKey {
+    (step.origin_pos(), step.to_pos())
+}
+
+/// 置换表中分数的类型
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Bound {
+    Exact,
+    /// 实际分数不低于此值（发生了剪枝）
+    Lower,
+    /// 实际分数不高于此值（没有一步超过alpha）
+    Upper,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct TTEntry {
+    pub depth: u8,
+    /// 胜负分数相对本节点
+    pub score: Score,
+    pub bound: Bound,
+    pub best: Option<StepKey>,
+}
+
+/// 置换表、杀手着法与历史表，用来给搜索排序
+pub struct Order {
+    /// key为position_key而不是标准形：搜索不要求棋盘有Symmetry，且每个节点做8次变换比省下的搜索更贵
+    tt: HashMap<u64, TTEntry>,
+    /// 每层两个杀手着法
+    killers: Vec<[Option<StepKey>; 2]>,
+    /// 双方各自的历史得分
+    history: [HashMap<StepKey, u32>; 2],
+}
+
+impl Order {
+    /// 置换表条目上限，超过后不再存入
+    const TT_SIZE: usize = 1 << 20;
+
+    pub fn new() -> Self {
+        Order { tt: HashMap::new(), killers: vec![], history: [HashMap::new(), HashMap::new()] }
+    }
+
+    pub fn probe(&self, key: u64) -> Option<TTEntry> {
+        self.tt.get(&key).copied()
+    }
+
+    /// 深度更深的结果才覆盖旧的
+    pub fn store(&mut self, key: u64, entry: TTEntry) {
+        if self.tt.len() >= Self::TT_SIZE && !self.tt.contains_key(&key) {
+            return;
+        }
+        match self.tt.get(&key) {
+            Some(old) if old.depth > entry.depth => (),
+            _ => { self.tt.insert(key, entry); },
+        }
+    }
+
+    /// 引起剪枝的步子记为杀手着法，并增加历史得分
+    pub fn cutoff<S: Step>(&mut self, step: &S, player: Player, ply: usize, depth: u8) {
+        let key = step_key(step);
+        if self.killers.len() <= ply {
+            self.killers.resize(ply + 1, [None; 2]);
+        }
+        let k = &mut self.killers[ply];
+        if k[0] != Some(key) {
+            k[1] = k[0];
+            k[0] = Some(key);
+        }
+        *self.history[player.0 as usize].entry(key).or_insert(0) += depth as u32 * depth as u32;
+    }
+
+    /// 排序：置换表着法，杀手着法，再按历史得分；同分保持原顺序
+    pub fn sort<S: Step>(&self, steps: &mut [S], player: Player, ply: usize, tt_best: Option<StepKey>) {
+        let killers = self.killers.get(ply).copied().unwrap_or([None; 2]);
+        let history = &self.history[player.0 as usize];
+        steps.sort_by_cached_key(|s| {
+            let key = step_key(s);
+            if Some(key) == tt_best {
+                (0, 0)
+            } else if Some(key) == killers[0] {
+                (1, 0)
+            } else if Some(key) == killers[1] {
+                (2, 0)
+            } else {
+                (3, -(*history.get(&key).unwrap_or(&0) as i64))
+            }
+        });
+    }
+}
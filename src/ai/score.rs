@@ -0,0 +1,104 @@
+use std::{fmt, ops::{Neg, Add, Sub}};
+
+/// 搜索用的分数
+/// 普通局面为评分函数的值，分出胜负的局面为WIN减去到终局的步数，越快赢分越高，越慢输分越高
+#[derive(PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Debug, Default, Hash)]
+pub struct Score(pub i32);
+
+impl Score {
+    /// 上下界，用i32::MAX而不是i32::MIN，保证取负不溢出
+    pub const INF: Score = Score(i32::MAX);
+    pub const DRAW: Score = Score(0);
+    /// 0步胜（已经赢了）
+    pub const WIN: Score = Score(1 << 30);
+    /// 胜负分数能表示的最大步数，评分函数的值不会超过WIN - MAX_PLY
+    pub const MAX_PLY: i32 = 1 << 16;
+
+    /// 评分函数的值，截断到胜负分数之下
+    pub fn eval(v: i32) -> Score {
+        let m = Self::WIN.0 - Self::MAX_PLY - 1;
+        Score(v.clamp(-m, m))
+    }
+
+    /// ply步后获胜
+    pub fn win_in(ply: usize) -> Score {
+        Score(Self::WIN.0 - (ply as i32).min(Self::MAX_PLY))
+    }
+
+    /// ply步后落败
+    pub fn loss_in(ply: usize) -> Score {
+        -Self::win_in(ply)
+    }
+
+    pub fn is_win(self) -> bool {
+        self.0 >= Self::WIN.0 - Self::MAX_PLY && self != Self::INF
+    }
+
+    pub fn is_loss(self) -> bool {
+        (-self).is_win()
+    }
+
+    /// 到终局的步数，正为胜，负为负，没分出胜负时为None
+    pub fn mate_distance(self) -> Option<i32> {
+        if self.is_win() {
+            Some(Self::WIN.0 - self.0)
+        } else if self.is_loss() {
+            Some(-(Self::WIN.0 + self.0))
+        } else {
+            None
+        }
+    }
+
+    /// 存入置换表时换成相对当前节点的步数，ply为当前节点距根节点的步数
+    pub fn to_node(self, ply: usize) -> Score {
+        match self {
+            s if s.is_win() => Score(s.0 + ply as i32),
+            s if s.is_loss() => Score(s.0 - ply as i32),
+            s => s,
+        }
+    }
+
+    /// 从置换表取出时换回相对根节点的步数
+    pub fn to_root(self, ply: usize) -> Score {
+        match self {
+            s if s.is_win() => Score(s.0 - ply as i32),
+            s if s.is_loss() => Score(s.0 + ply as i32),
+            s => s,
+        }
+    }
+}
+
+impl Neg for Score {
+    type Output = Score;
+
+    fn neg(self) -> Score {
+        Score(-self.0)
+    }
+}
+
+/// 加减一个数（渴望窗口、零窗口），结果不超出[-INF, INF]
+impl Add<i32> for Score {
+    type Output = Score;
+
+    fn add(self, rhs: i32) -> Score {
+        Score(self.0.saturating_add(rhs).max(-i32::MAX))
+    }
+}
+
+impl Sub<i32> for Score {
+    type Output = Score;
+
+    fn sub(self, rhs: i32) -> Score {
+        Score(self.0.saturating_sub(rhs).max(-i32::MAX))
+    }
+}
+
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mate_distance() {
+            Some(d) if d >= 0 => write!(f, "{}步胜", d),
+            Some(d) => write!(f, "{}步负", -d),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
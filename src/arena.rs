@@ -0,0 +1,101 @@
+// 不用界面的对局：两个座位（电脑、外部引擎等）连续下若干局，交换先后手，统计胜负
+
+use std::{thread, time::Duration};
+
+use crate::{base::{Game, Board, Step, Player, GameState, OutCome}, agent::{self, Agent, Input}};
+
+/// 两个座位都没有动作时等一会再问
+const IDLE: Duration = Duration::from_millis(10);
+
+/// 座位agents[i]执Player(i)，下到对局结束。不合法的走法按认输处理
+pub fn play<B: Board<S>, S: Step>(game: &mut Game<B, S>, agents: &mut [Box<dyn Agent<B, S> + '_>; 2]) {
+    let mut request = None;
+    while game.state == GameState::Running {
+        if game.check_time() {
+            break;
+        }
+        let curr = game.curr_player.0 as usize;
+        let mut acted = false;
+        for i in [curr, 1 - curr] {
+            let me = Player(i as u8);
+            let asked = request.filter(|(from, _)| *from != me).map(|(_, r)| r);
+            let Some(action) = agents[i].poll(game, me, &Input::None, asked) else {
+                continue;
+            };
+            if agent::apply(game, me, &action, &mut request) {
+                agents[1 - i].observe(game, &action);
+            } else {
+                game.resign(me);
+            }
+            acted = true;
+        }
+        if !acted {
+            thread::sleep(IDLE);
+        }
+    }
+}
+
+/// 座位甲、乙的战绩
+#[derive(PartialEq, Eq, Default, Clone, Copy, Debug)]
+pub struct Tally {
+    pub wins: [u32; 2],
+    pub draws: u32,
+}
+
+/// 下games局，甲在单数局执先，双数局执后。每局开始时new_game给出对局，结束后交给report（局号从0起，甲执哪一方）
+pub fn run<B: Board<S>, S: Step>(games: usize, mut new_game: impl FnMut() -> Game<B, S>,
+    agents: [Box<dyn Agent<B, S> + '_>; 2], mut report: impl FnMut(usize, &Game<B, S>, Player)) -> Tally {
+
+    let mut tally = Tally::default();
+    let mut agents = agents;
+    for i in 0..games {
+        // agents[0]执先；甲为Player(i % 2)
+        let first = Player((i % 2) as u8);
+        let mut game = new_game();
+        play(&mut game, &mut agents);
+        let winner = match game.state {
+            GameState::Over(OutCome::Winer(p)) => Some(p),
+            GameState::Over(OutCome::Timeout(p)) => Some(p.rev()),
+            _ => None,
+        };
+        match winner {
+            Some(p) => tally.wins[(p != first) as usize] += 1,
+            None => tally.draws += 1,
+        }
+        report(i, &game, first);
+        agents.swap(0, 1);
+    }
+    tally
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ai::Level, agent::{Computer, External, Protocol}, base::GameType, tic_tac_toe::{TTTBoard, TTTStep}};
+
+    fn ttt() -> Game<TTTBoard, TTTStep> {
+        Game::new(TTTBoard::new(), GameType::Put)
+    }
+
+    #[test]
+    fn perfect_play_draws() {
+        let agents: [Box<dyn Agent<TTTBoard, TTTStep>>; 2] =
+            [Box::new(Computer::new(Level::FULL, None)), Box::new(Computer::new(Level::FULL, None))];
+        let mut firsts = vec![];
+        let tally = run(2, ttt, agents, |_, g, first| {
+            assert!(g.state == GameState::Over(OutCome::Draw));
+            firsts.push(first);
+        });
+        assert_eq!(tally, Tally { wins: [0, 0], draws: 2 });
+        assert_eq!(firsts, [Player(0), Player(1)]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn broken_engine_loses() {
+        let agents: [Box<dyn Agent<TTTBoard, TTTStep>>; 2] =
+            [Box::new(Computer::new(Level::FULL, None)), Box::new(External::new("exit 0", Protocol::Text, Duration::from_secs(1)))];
+        let tally = run(2, ttt, agents, |_, _, _| ());
+        assert_eq!(tally, Tally { wins: [2, 0], draws: 0 });
+    }
+}
@@ -1,10 +1,8 @@
-#[derive(PartialEq, Copy, Clone, Debug)]
-pub enum Role {
-    Hum,
-    Com,
-}
+use std::{collections::hash_map::DefaultHasher, hash::{Hash, Hasher}, time::Instant};
 
-#[derive(PartialEq, Copy, Clone, Debug)]
+use crate::clock::{Clock, TimeControl};
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone, Debug)]
 pub struct Player(pub u8);
 
 impl Player {
@@ -21,6 +19,8 @@ impl Player {
 pub enum OutCome {
     Draw,
     Winer(Player),
+    /// 这一方超时判负
+    Timeout(Player),
 }
 
 #[derive(PartialEq)]
@@ -39,28 +39,279 @@ pub enum GameType {
     Move,
 }
 
+/// 和棋规则，只对GameType::Move生效
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct DrawRule {
+    /// 同一局面（含行棋方）出现几次判和，0表示不启用
+    pub repeat: u8,
+    /// 连续多少步没有吃子判和，0表示不启用
+    pub no_eat: u16,
+}
+
+impl Default for DrawRule {
+    fn default() -> Self {
+        DrawRule { repeat: 3, no_eat: 50 }
+    }
+}
+
+/// 走棋类游戏的局面记录，供判和与AI搜索使用
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    /// 每一步之后的局面key（含行棋方），第一个为开局局面
+    pub keys: Vec<u64>,
+    /// 距离上次吃子过了多少步
+    pub no_eat: u16,
+}
+
+impl History {
+    pub fn new<B: Board<S>, S: Step>(board: &B, next: Player) -> Self {
+        History { keys: vec![position_key(board, next)], no_eat: 0 }
+    }
+
+    /// 记录一步棋后的局面，eat表示这步棋是否吃子
+    pub fn push(&mut self, key: u64, eat: bool) {
+        self.keys.push(key);
+        self.no_eat = if eat { 0 } else { self.no_eat + 1 };
+    }
+
+    /// 某局面出现过的次数
+    pub fn count(&self, key: u64) -> usize {
+        self.keys.iter().filter(|k| **k == key).count()
+    }
+
+    /// 按规则判断最后一个局面是否和棋
+    pub fn is_draw(&self, rule: &DrawRule) -> bool {
+        let last = match self.keys.last() {
+            Some(k) => *k,
+            None => return false,
+        };
+        (rule.repeat > 0 && self.count(last) >= rule.repeat as usize) ||
+        (rule.no_eat > 0 && self.no_eat >= rule.no_eat)
+    }
+}
+
+/// 局面key，把轮到谁走也算进去
+/// 搜索时每个节点都要算，所以只做一次乘法混合
+pub fn position_key<B: Board<S>, S: Step>(board: &B, next: Player) -> u64 {
+    board.key() ^ (next.0 as u64 + 1).wrapping_mul(0x9e3779b97f4a7c15)
+}
+
+/// 辅助各棋盘实现Board::key
+pub fn hash_key<T: Hash>(t: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    t.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// 正方形棋盘的8种对称变换，0..4为顺时针旋转0/90/180/270度，4..8为旋转后再左右翻转
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub struct Sym(pub u8);
+
+impl Sym {
+    pub const ALL: [Sym; 8] = [Sym(0), Sym(1), Sym(2), Sym(3), Sym(4), Sym(5), Sym(6), Sym(7)];
+
+    /// 把边长为size的棋盘上的pos做变换
+    pub fn pos(&self, size: u8, pos: (u8, u8)) -> (u8, u8) {
+        let (mut x, mut y) = pos;
+        for _ in 0..self.0 % 4 {
+            (x, y) = (y, size - 1 - x);
+        }
+        if self.0 >= 4 {
+            y = size - 1 - y;
+        }
+        (x, y)
+    }
+
+    /// 逆变换：翻转的逆是自己，旋转的逆是反向旋转
+    pub fn inverse(&self) -> Sym {
+        if self.0 >= 4 {
+            *self
+        } else {
+            Sym((4 - self.0) % 4)
+        }
+    }
+}
+
+/// 有对称性的正方形棋盘，规则在8种变换下不变
+pub trait Symmetry<S: Step>: Board<S> {
+    /// 棋盘边长
+    fn side(&self) -> u8;
+
+    /// 按行展开的所有格子
+    fn cells(&self) -> Vec<Option<Player>>;
+
+    /// 变换后的新棋盘，派生状态（如评分）需要重建
+    fn transform(&self, t: Sym) -> Self;
+
+    /// 步子做同样的变换
+    fn transform_step(&self, step: &S, t: Sym) -> S;
+
+    /// 变换后按行展开的格子
+    fn sym_cells(&self, t: Sym) -> Vec<Option<Player>> {
+        let size = self.side();
+        let cells = self.cells();
+        let mut v = vec![None; cells.len()];
+        for (i, c) in cells.into_iter().enumerate() {
+            let (x, y) = t.pos(size, ((i / size as usize) as u8, (i % size as usize) as u8));
+            v[x as usize * size as usize + y as usize] = c;
+        }
+        v
+    }
+
+    /// 标准形所用的变换：变换后格子序列最小的那个
+    fn canonical_sym(&self) -> Sym {
+        Sym::ALL.into_iter().min_by_key(|t| self.sym_cells(*t)).unwrap()
+    }
+
+    /// 标准形及所用的变换
+    fn canonical(&self) -> (Self, Sym) where Self: Sized {
+        let t = self.canonical_sym();
+        (self.transform(t), t)
+    }
+
+    /// 变换后的局面加上行棋方的key，用stable_hash，可以写入文件
+    fn sym_key(&self, t: Sym, next: Player) -> u64 {
+        stable_hash(cell_bytes(&self.sym_cells(t)).chain([next.0]))
+    }
+
+    /// 标准形的key，对称的局面相同
+    fn canonical_key(&self, next: Player) -> u64 {
+        self.sym_key(self.canonical_sym(), next)
+    }
+}
+
+/// FNV-1a，结果不随编译器版本变化。DefaultHasher不保证这一点，写入文件的key都用它
+pub fn stable_hash<I: IntoIterator<Item = u8>>(bytes: I) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for b in bytes {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    h
+}
+
+/// 空格为0，Player(i)为i+1
+fn cell_bytes(cells: &[Option<Player>]) -> impl Iterator<Item = u8> + '_ {
+    cells.iter().map(|c| c.map_or(0, |p| p.0 + 1))
+}
+
+/// 对局的key：所有变换下整个局面序列的哈希取最小，对称的两局棋key相同
+pub fn game_key<B: Symmetry<S>, S: Step>(start: &B, steps: &[S]) -> u64 {
+    let mut boards = vec![start.clone()];
+    for s in steps {
+        let b = boards.last().unwrap().copy_put(*s).unwrap();
+        boards.push(b);
+    }
+    Sym::ALL.into_iter().map(|t| {
+        let seq: Vec<_> = boards.iter().map(|b| b.sym_cells(t)).collect();
+        stable_hash(seq.iter().flat_map(|cells| cell_bytes(cells)))
+    }).min().unwrap()
+}
+
 pub struct Game<B: Board<S>, S: Step> {
     pub state: GameState,
     pub board: B,
-    pub players: [Role; 2],
     pub curr_player: Player,    // 此时还未放棋子，用下标表示
     pub hist_steps: Vec<S>,
     pub game_type: GameType,
+    pub draw_rule: DrawRule,
+    pub history: History,
+    /// 开局时的棋盘，用于复盘
+    pub start: B,
+    /// 棋钟，不计时为None
+    pub clock: Option<Clock>,
 }
 
 impl<B: Board<S>,S: Step> Game<B,S> {
     /// 创建一局新游戏
-    pub fn new(board: B, players: [Role; 2], game_type: GameType) -> Self {
+    pub fn new(board: B, game_type: GameType) -> Self {
+        let history = History::new(&board, Player(0));
         Game {
             state: GameState::Running,
-            board,
-            players,
             curr_player: Player(0),
             hist_steps: vec![],
             game_type,
+            draw_rule: DrawRule::default(),
+            history,
+            start: board.clone(),
+            board,
+            clock: None,
+        }
+    }
+
+    /// 双方按同样的用时规则计时，从现在开始
+    pub fn with_clock(mut self, control: TimeControl) -> Self {
+        self.clock = Some(Clock::new(control, Instant::now()));
+        self
+    }
+
+    /// 从摆好的局面开始时指定先走的一方
+    pub fn with_next_player(mut self, next: Player) -> Self {
+        self.curr_player = next;
+        self.history = History::new(&self.board, next);
+        self
+    }
+
+    /// 换成摆好的局面重新开始，保留双方设置与规则
+    pub fn reset_to(&mut self, board: B, next: Player) {
+        self.state = board.over();
+        self.curr_player = next;
+        self.hist_steps.clear();
+        self.history = History::new(&board, next);
+        self.start = board.clone();
+        self.board = board;
+        if let Some(c) = &mut self.clock {
+            *c = Clock::new(c.control, Instant::now());
         }
     }
 
+    /// 认输
+    pub fn resign(&mut self, player: Player) {
+        if self.state == GameState::Running {
+            self.state = GameState::Over(OutCome::Winer(player.rev()));
+        }
+    }
+
+    /// 双方同意和棋
+    pub fn agree_draw(&mut self) {
+        if self.state == GameState::Running {
+            self.state = GameState::Over(OutCome::Draw);
+        }
+    }
+
+    /// 悔棋：撤回player的上一步，对方已经应了的话连应手一起撤回，返回撤回的步数。
+    /// 从开局局面重走剩下的步子，判和记录随之重建
+    pub fn undo(&mut self, player: Player) -> usize {
+        let Some(i) = self.hist_steps.iter().rposition(|s| s.who() == player) else {
+            return 0;
+        };
+        let steps: Vec<S> = self.hist_steps.drain(i..).collect();
+        let kept = std::mem::take(&mut self.hist_steps);
+        let first = kept.first().or(steps.first()).map_or(self.curr_player, |s| s.who());
+        let clock = self.clock.take();
+        self.reset_to(self.start.clone(), first);
+        for s in kept {
+            self.step(s);
+        }
+        self.clock = clock;
+        steps.len()
+    }
+
+    /// 轮到的一方已经超时则判负，返回是否判了
+    pub fn check_time(&mut self) -> bool {
+        let flagged = self.clock.as_ref().is_some_and(|c| c.flagged(self.curr_player, Instant::now()));
+        if flagged && self.state == GameState::Running {
+            self.state = GameState::Over(OutCome::Timeout(self.curr_player));
+            return true;
+        }
+        false
+    }
+
+    /// 对局的key，用来找出重复（含对称）的对局
+    pub fn record_key(&self) -> u64 where B: Symmetry<S> {
+        game_key(&self.start, &self.hist_steps)
+    }
+
     /// 下一个执棋的玩家
     fn next_player(&self) -> Player {
         self.curr_player.rev()
@@ -71,11 +322,26 @@ impl<B: Board<S>,S: Step> Game<B,S> {
         if let GameState::Over(_) = self.state {
             return false;
         }
+        let num = self.board.pieces_count();
         let result = self.board.put(step);
         if result {
             self.state = self.board.over();
+            // 这步用时超了，即使成五也按超时判负
+            if let Some(c) = &mut self.clock {
+                if !c.press(self.curr_player, Instant::now()) {
+                    self.state = GameState::Over(OutCome::Timeout(self.curr_player));
+                }
+            }
             self.curr_player = self.next_player();
             self.hist_steps.push(step);
+
+            if self.game_type == GameType::Move {
+                let eat = self.board.pieces_count() < num;
+                self.history.push(position_key(&self.board, self.curr_player), eat);
+                if self.state == GameState::Running && self.history.is_draw(&self.draw_rule) {
+                    self.state = GameState::Over(OutCome::Draw);
+                }
+            }
         }
         result
     }
@@ -86,7 +352,8 @@ impl<B: Board<S>,S: Step> Game<B,S> {
 //     None
 // }
 
-pub trait Board<S>: Clone {
+/// 多线程搜索时在线程间共享，要求Send + Sync
+pub trait Board<S>: Clone + Send + Sync {
     /// 创建一个新棋盘
     fn new() -> Self;
 
@@ -96,6 +363,12 @@ pub trait Board<S>: Clone {
     /// 判断当前是否意味着结束
     fn over(&self) -> GameState;
 
+    /// 局面的哈希值，只与棋子分布有关，用于判断重复局面
+    fn key(&self) -> u64;
+
+    /// 棋盘上棋子的数量，用于判断是否吃子
+    fn pieces_count(&self) -> u16;
+
     // 移入ai中
     // fn get_possible_steps(&self, player: Player) -> Vec<S>;
 
@@ -110,11 +383,242 @@ pub trait Board<S>: Clone {
     }
 }
 
-pub trait Step: Copy {
+pub trait Step: Copy + Send + Sync {
     /// 这步棋属于谁
     fn who(&self) -> Player;
 
     fn new_put_step(pos: (u8, u8), p: Player) -> Self;
 
     fn new_move_step(from: (u8, u8), to: (u8, u8), p: Player) -> Self;
+
+    /// 落子的位置，或走棋的终点
+    fn to_pos(&self) -> (u8, u8);
+
+    /// 走棋的起点，落子类为None
+    fn origin_pos(&self) -> Option<(u8, u8)>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tic_tac_toe::{TTTBoard, TTTStep}, goT::{GoTBoard, GoTStep}};
+
+    fn put(p: u8, pos: (u8, u8)) -> TTTStep {
+        TTTStep::new_put_step(pos, Player(p))
+    }
+
+    fn mv(p: u8, from: (u8, u8), to: (u8, u8)) -> GoTStep {
+        GoTStep::new_move_step(from, to, Player(p))
+    }
+
+    #[test]
+    fn step_alternates_and_rejects_illegal() {
+        let mut game = Game::new(TTTBoard::new(), GameType::Put);
+        assert!(game.step(put(0, (1, 1))));
+        assert_eq!(game.curr_player, Player(1));
+        // 落在已有棋子上：不改变任何状态
+        assert!(!game.step(put(1, (1, 1))));
+        assert_eq!(game.curr_player, Player(1));
+        assert_eq!(game.hist_steps.len(), 1);
+        assert!(game.state == GameState::Running);
+        assert!(game.step(put(1, (0, 0))));
+        assert_eq!(game.curr_player, Player(0));
+        assert_eq!(game.hist_steps.len(), 2);
+    }
+
+    #[test]
+    fn step_stops_after_game_over() {
+        let mut game = Game::new(TTTBoard::new(), GameType::Put);
+        for (p, pos) in [(0, (0, 0)), (1, (1, 0)), (0, (0, 1)), (1, (1, 1))] {
+            assert!(game.step(put(p, pos)));
+        }
+        assert!(game.step(put(0, (0, 2))));
+        assert!(game.state == GameState::Over(OutCome::Winer(Player(0))));
+        assert!(!game.step(put(1, (2, 2))));
+        assert_eq!(game.hist_steps.len(), 5);
+    }
+
+    #[test]
+    fn loss_on_time() {
+        use std::{thread::sleep, time::Duration};
+        let control = TimeControl::SuddenDeath { total: Duration::from_millis(20) };
+        let mut game = Game::new(TTTBoard::new(), GameType::Put).with_clock(control);
+        assert!(game.step(put(0, (1, 1))));
+        assert!(!game.check_time());
+        sleep(Duration::from_millis(30));
+        assert!(game.check_time());
+        assert!(game.state == GameState::Over(OutCome::Timeout(Player(1))));
+        assert!(!game.step(put(1, (0, 0))));
+
+        // 走完一步才超时也判负
+        let mut game = Game::new(TTTBoard::new(), GameType::Put).with_clock(control);
+        sleep(Duration::from_millis(30));
+        assert!(game.step(put(0, (1, 1))));
+        assert!(game.state == GameState::Over(OutCome::Timeout(Player(0))));
+    }
+
+    #[test]
+    fn undo_and_resign() {
+        let mut game = Game::new(TTTBoard::new(), GameType::Put);
+        assert_eq!(game.undo(Player(0)), 0);
+        for (p, pos) in [(0, (0, 0)), (1, (1, 0)), (0, (0, 1)), (1, (1, 1))] {
+            assert!(game.step(put(p, pos)));
+        }
+        // 对方已经应了，连应手一起撤回
+        assert_eq!(game.undo(Player(0)), 2);
+        assert_eq!(game.curr_player, Player(0));
+        assert_eq!(game.hist_steps.len(), 2);
+        // 轮到对方时撤回自己刚走的一步，已经结束的对局也能撤回
+        assert!(game.step(put(0, (0, 1))));
+        assert!(game.step(put(1, (2, 2))));
+        assert!(game.step(put(0, (0, 2))));
+        assert!(game.state == GameState::Over(OutCome::Winer(Player(0))));
+        assert_eq!(game.undo(Player(0)), 1);
+        assert!(game.state == GameState::Running);
+        assert_eq!(game.curr_player, Player(0));
+        assert_eq!(game.board.key(), {
+            let mut b = TTTBoard::new();
+            for (p, pos) in [(0, (0, 0)), (1, (1, 0)), (0, (0, 1)), (1, (2, 2))] {
+                b.put(put(p, pos));
+            }
+            b.key()
+        });
+
+        game.resign(Player(0));
+        assert!(game.state == GameState::Over(OutCome::Winer(Player(1))));
+        // 结束后再认输不改变结果
+        game.resign(Player(1));
+        assert!(game.state == GameState::Over(OutCome::Winer(Player(1))));
+    }
+
+    #[test]
+    fn repetition_draw() {
+        let mut game = Game::new(GoTBoard::new(), GameType::Move);
+        let cycle = [mv(0, (0, 0), (1, 0)), mv(1, (3, 3), (2, 3)), mv(0, (1, 0), (0, 0)), mv(1, (2, 3), (3, 3))];
+        for s in cycle.iter().chain(cycle.iter()) {
+            assert!(game.state == GameState::Running);
+            assert!(game.step(*s));
+        }
+        // 开局局面第三次出现
+        assert_eq!(game.history.count(*game.history.keys.last().unwrap()), 3);
+        assert!(game.state == GameState::Over(OutCome::Draw));
+        assert!(!game.step(cycle[0]));
+    }
+
+    #[test]
+    fn history_is_draw() {
+        let rule = DrawRule { repeat: 3, no_eat: 4 };
+        assert!(!History::default().is_draw(&rule));
+        let mut h = History { keys: vec![1], no_eat: 0 };
+        for (key, eat) in [(2, false), (1, false), (2, false)] {
+            h.push(key, eat);
+            assert!(!h.is_draw(&rule));
+        }
+        // 局面1第三次出现
+        h.push(1, false);
+        assert_eq!(h.count(1), 3);
+        assert!(h.is_draw(&rule));
+        // 只看最后一个局面
+        h.push(3, true);
+        assert!(!h.is_draw(&rule));
+        assert_eq!(h.no_eat, 0);
+        for key in 4..8 {
+            h.push(key, false);
+        }
+        assert_eq!(h.no_eat, 4);
+        assert!(h.is_draw(&rule));
+        // 规则为0时不启用
+        assert!(!h.is_draw(&DrawRule { repeat: 3, no_eat: 0 }));
+        h.push(1, false);
+        assert!(!h.is_draw(&DrawRule { repeat: 0, no_eat: 0 }));
+    }
+
+    #[test]
+    fn eat_resets_no_eat_count() {
+        let mut game = Game::new(crate::position::parse("o.../.o../x.../..xx", &GoTBoard::new()).unwrap().0, GameType::Move);
+        game.draw_rule = DrawRule { repeat: 0, no_eat: 2 };
+        assert!(game.step(mv(0, (1, 1), (1, 0))));
+        assert_eq!(game.history.no_eat, 0);
+        assert!(game.step(mv(1, (3, 2), (2, 2))));
+        assert!(game.state == GameState::Running);
+        assert!(game.step(mv(0, (1, 0), (1, 1))));
+        assert!(game.state == GameState::Over(OutCome::Draw));
+    }
+
+    #[test]
+    fn no_eat_draw_only_for_move_games() {
+        let mut game = Game::new(GoTBoard::new(), GameType::Move);
+        game.draw_rule = DrawRule { repeat: 0, no_eat: 3 };
+        assert!(game.step(mv(0, (0, 0), (1, 0))));
+        assert!(game.step(mv(1, (3, 0), (2, 0))));
+        assert_eq!(game.history.no_eat, 2);
+        assert!(game.step(mv(0, (1, 0), (1, 1))));
+        assert!(game.state == GameState::Over(OutCome::Draw));
+
+        // Put类游戏不判和
+        let mut game = Game::new(TTTBoard::new(), GameType::Put);
+        game.draw_rule = DrawRule { repeat: 1, no_eat: 1 };
+        assert!(game.step(put(0, (0, 0))));
+        assert!(game.state == GameState::Running);
+    }
+
+    #[test]
+    fn reset_to_edited_position() {
+        let mut game = Game::new(TTTBoard::new(), GameType::Put);
+        assert!(game.step(put(0, (1, 1))));
+        let (board, next) = crate::position::parse("oo./xx./3 x", &TTTBoard::new()).unwrap();
+        game.reset_to(board, next);
+        assert!(game.hist_steps.is_empty());
+        assert_eq!(game.curr_player, Player(1));
+        assert!(game.step(put(1, (1, 2))));
+        assert!(game.state == GameState::Over(OutCome::Winer(Player(1))));
+
+        // 摆出已分胜负的局面
+        let (board, next) = crate::position::parse("ooo/xx./3 x", &TTTBoard::new()).unwrap();
+        game.reset_to(board, next);
+        assert!(game.state == GameState::Over(OutCome::Winer(Player(0))));
+    }
+
+    #[test]
+    fn canonical_key_is_stable_and_symmetric() {
+        // 固定的值：换编译器版本也不能变，开局库文件靠它
+        assert_eq!(TTTBoard::new().canonical_key(Player(0)), 0x69d307cc20f6ef8d);
+        let mut b = TTTBoard::new();
+        b.put(put(0, (0, 1)));
+        b.put(put(1, (0, 0)));
+        let key = b.canonical_key(Player(0));
+        for t in Sym::ALL {
+            let tb = b.transform(t);
+            assert_eq!(tb.canonical_key(Player(0)), key, "{:?}", t);
+            assert_eq!(tb.canonical().0.cells(), b.canonical().0.cells(), "{:?}", t);
+        }
+        assert_ne!(b.canonical_key(Player(1)), key);
+        b.put(put(0, (1, 1)));
+        assert_ne!(b.canonical_key(Player(0)), key);
+    }
+
+    #[test]
+    fn game_key_matches_symmetric_games() {
+        let start = TTTBoard::new();
+        let a = [put(0, (0, 0)), put(1, (1, 1)), put(0, (0, 1))];
+        let b: Vec<_> = a.iter().map(|s| start.transform_step(s, Sym(5))).collect();
+        assert_eq!(game_key(&start, &a), game_key(&start, &b));
+        // 同样的最终局面，顺序不同不算同一局
+        let c = [put(0, (0, 1)), put(1, (1, 1)), put(0, (0, 0))];
+        assert_ne!(game_key(&start, &a), game_key(&start, &c));
+        let mut game = Game::new(start.clone(), GameType::Put);
+        for s in b {
+            game.step(s);
+        }
+        assert_eq!(game.record_key(), game_key(&start, &a));
+    }
+
+    #[test]
+    fn sym_inverse_round_trips() {
+        for t in Sym::ALL {
+            for pos in [(0, 0), (0, 3), (1, 2), (3, 3)] {
+                assert_eq!(t.inverse().pos(4, t.pos(4, pos)), pos, "{:?}", t);
+            }
+        }
+    }
 }
@@ -0,0 +1,250 @@
+use std::{collections::HashMap, fs, io, path::Path, time::Duration};
+
+use rand::Rng;
+
+use crate::{base::{Player, Step, Symmetry, GameState, OutCome}, ai::{self, AI, Level, SearchCtx}, solver::Oracle, record};
+
+/// 标准形下的落子位置及权重
+type Moves = Vec<((u8, u8), u32)>;
+
+/// 开局库，以标准形局面为key，存标准形下的走法及权重
+pub struct Book {
+    table: HashMap<u64, Moves>,
+}
+
+/// 自对弈时AI的水平：每步最多想一秒，几百局也能跑完
+pub const SELF_PLAY_LEVEL: Level = Level { depth: 6, time: Some(Duration::from_secs(1)), ..Level::FULL };
+
+impl Book {
+    pub fn new() -> Self {
+        Book { table: HashMap::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    /// 记录局面下的一步棋，只支持落子类
+    pub fn add<B: Symmetry<S>, S: Step>(&mut self, board: &B, step: &S, weight: u32) {
+        let t = board.canonical_sym();
+        let pos = board.transform_step(step, t).to_pos();
+        let moves = self.table.entry(board.sym_key(t, step.who())).or_default();
+        match moves.iter_mut().find(|m| m.0 == pos) {
+            Some(m) => m.1 += weight,
+            None => moves.push((pos, weight)),
+        }
+    }
+
+    /// 局面下库中的走法及权重，已变换回当前局面
+    pub fn probe<B: Symmetry<S>, S: Step>(&self, board: &B, next: Player) -> Vec<(S, u32)> {
+        let t = board.canonical_sym();
+        match self.table.get(&board.sym_key(t, next)) {
+            Some(moves) => moves.iter().map(|(pos, w)| {
+                let s = S::new_put_step(*pos, next);
+                (board.transform_step(&s, t.inverse()), *w)
+            }).collect(),
+            None => vec![],
+        }
+    }
+
+    /// 按权重随机选一步，增加变化
+    pub fn choose<B: Symmetry<S>, S: Step, R: Rng>(&self, board: &B, next: Player, rng: &mut R) -> Option<S> {
+        let moves = self.probe(board, next);
+        let total: u32 = moves.iter().map(|m| m.1).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut r = rng.gen_range(0..total);
+        for (s, w) in moves {
+            if r < w {
+                return Some(s);
+            }
+            r -= w;
+        }
+        None
+    }
+
+    /// 从棋谱建库，只取前max_ply步；胜方的走法权重为2，和棋双方为1，负方不收录
+    pub fn add_record<B: Symmetry<S>, S: Step>(&mut self, start: &B, steps: &[S], max_ply: usize) {
+        let mut board = start.clone();
+        for s in steps {
+            board.put(*s);
+        }
+        let winner = match board.over() {
+            GameState::Over(OutCome::Winer(p)) => Some(p),
+            _ => None,
+        };
+
+        let mut board = start.clone();
+        for s in steps.iter().take(max_ply) {
+            let weight = match winner {
+                Some(p) if p == s.who() => 2,
+                Some(_) => 0,
+                None => 1,
+            };
+            if weight > 0 {
+                self.add(&board, s, weight);
+            }
+            board.put(*s);
+        }
+    }
+
+    /// 自对弈建库：前random_ply步从候选中随机选，之后由level的AI走完整局。重复的对局只收录一次，返回收录的局数
+    pub fn self_play<B: Symmetry<S> + AI<S>, S: Step, R: Rng>(&mut self, start: &B, games: usize,
+        random_ply: usize, max_ply: usize, level: &Level, rng: &mut R) -> usize {
+
+        let mut records = vec![];
+        for _ in 0..games {
+            let mut board = start.clone();
+            let mut p = Player(0);
+            let mut steps = vec![];
+            while board.over() == GameState::Running {
+                let step = if steps.len() < random_ply {
+                    let v = board.get_possible_steps(p, u8::MAX);
+                    let n = v.len().min(4);
+                    if n == 0 { None } else { Some(v[rng.gen_range(0..n)]) }
+                } else {
+                    ai::search_best_step(&board, p, level, SearchCtx::new(None))
+                };
+                match step {
+                    Some(s) if board.put(s) => steps.push(s),
+                    _ => break,
+                }
+                p = p.rev();
+            }
+            records.push(steps);
+        }
+        record::dedup(start, &mut records);
+        for steps in &records {
+            self.add_record(start, steps, max_ply);
+        }
+        records.len()
+    }
+
+    /// 格式：一行一个局面，key(16位十六进制)后跟若干`x,y:权重`
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut keys: Vec<_> = self.table.keys().collect();
+        keys.sort();
+        let mut text = String::new();
+        for k in keys {
+            text += &format!("{:016x}", k);
+            for ((x, y), w) in &self.table[k] {
+                text += &format!(" {},{}:{}", x, y, w);
+            }
+            text += "\n";
+        }
+        fs::write(path, text)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let bad = || io::Error::new(io::ErrorKind::InvalidData, "bad book file");
+        let mut table = HashMap::new();
+        for line in fs::read_to_string(path)?.lines() {
+            let mut words = line.split_whitespace();
+            let key = match words.next() {
+                Some(k) => u64::from_str_radix(k, 16).map_err(|_| bad())?,
+                None => continue,
+            };
+            let mut moves = vec![];
+            for w in words {
+                let (pos, weight) = w.split_once(':').ok_or_else(bad)?;
+                let pos = record::parse_pos(pos).ok_or_else(bad)?;
+                moves.push((pos, weight.parse().map_err(|_| bad())?));
+            }
+            table.insert(key, moves);
+        }
+        Ok(Book { table })
+    }
+}
+
+impl<B: Symmetry<S>, S: Step> Oracle<B, S> for Book {
+    /// 库里的走法，没有则交给搜索
+    fn best_step(&self, board: &B, next: Player) -> Option<S> {
+        self.choose(board, next, &mut rand::thread_rng())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, rngs::StdRng};
+    use crate::{base::{Board, Sym}, tic_tac_toe::{TTTBoard, TTTStep}};
+
+    fn put(p: u8, pos: (u8, u8)) -> TTTStep {
+        TTTStep::new_put_step(pos, Player(p))
+    }
+
+    fn positions(moves: Vec<(TTTStep, u32)>) -> Vec<((u8, u8), u32)> {
+        let mut v: Vec<_> = moves.into_iter().map(|(s, w)| (s.to_pos(), w)).collect();
+        v.sort();
+        v
+    }
+
+    #[test]
+    fn probe_through_symmetry() {
+        let mut book = Book::new();
+        let start = TTTBoard::new();
+        book.add(&start, &put(0, (0, 0)), 1);
+        // 同一步权重累加
+        book.add(&start, &put(0, (0, 0)), 2);
+        book.add(&start, &put(0, (1, 1)), 1);
+        assert_eq!(book.len(), 1);
+        assert_eq!(positions(book.probe(&start, Player(0))), [((0, 0), 3), ((1, 1), 1)]);
+        assert!(book.probe(&start, Player(1)).is_empty());
+
+        // 对称的局面查到变换回来的走法
+        let b = start.copy_put(put(0, (0, 0))).unwrap().copy_put(put(1, (0, 1))).unwrap();
+        book.add(&b, &put(0, (1, 1)), 1);
+        book.add(&b, &put(0, (2, 0)), 2);
+        for t in Sym::ALL {
+            let tb = b.transform(t);
+            let mut expect = vec![(b.transform_step(&put(0, (1, 1)), t).to_pos(), 1), (b.transform_step(&put(0, (2, 0)), t).to_pos(), 2)];
+            expect.sort();
+            assert_eq!(positions(book.probe(&tb, Player(0))), expect, "{:?}", t);
+        }
+    }
+
+    #[test]
+    fn choose_by_weight() {
+        let mut book = Book::new();
+        let start = TTTBoard::new();
+        book.add(&start, &put(0, (1, 1)), 3);
+        book.add(&start, &put(0, (0, 1)), 1);
+        let mut rng = StdRng::seed_from_u64(1);
+        let center = (0..400).filter(|_| book.choose(&start, Player(0), &mut rng).unwrap().to_pos() == (1, 1)).count();
+        assert!((250..350).contains(&center), "{}", center);
+        assert!(book.choose(&start.copy_put(put(0, (1, 1))).unwrap(), Player(1), &mut rng).is_none());
+    }
+
+    #[test]
+    fn records_weight_winner() {
+        let start = TTTBoard::new();
+        let mut book = Book::new();
+        // 先手赢：先手的步子权重2，后手的不收录
+        let win = [put(0, (0, 0)), put(1, (1, 0)), put(0, (0, 1)), put(1, (1, 1)), put(0, (0, 2))];
+        book.add_record(&start, &win, 3);
+        assert_eq!(positions(book.probe(&start, Player(0))), [((0, 0), 2)]);
+        let b = start.copy_put(win[0]).unwrap();
+        assert!(book.probe(&b, Player(1)).is_empty());
+        // 只取前max_ply步
+        let b = b.copy_put(win[1]).unwrap().copy_put(win[2]).unwrap().copy_put(win[3]).unwrap();
+        assert!(book.probe(&b, Player(0)).is_empty());
+    }
+
+    #[test]
+    fn self_play_and_save_load() {
+        let start = TTTBoard::new();
+        let mut book = Book::new();
+        let level = Level { depth: 4, ..Level::FULL };
+        let n = book.self_play(&start, 6, 1, 4, &level, &mut StdRng::seed_from_u64(2));
+        assert!((1..=6).contains(&n));
+        assert!(!book.probe(&start, Player(0)).is_empty());
+
+        let path = std::env::temp_dir().join(format!("rustbang-book-{}.book", std::process::id()));
+        book.save(&path).unwrap();
+        let loaded = Book::load(&path);
+        let _ = std::fs::remove_file(&path);
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.table, book.table);
+    }
+}
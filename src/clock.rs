@@ -0,0 +1,239 @@
+// 对局的计时
+//
+// 用时写法：数字后跟s、m、h（不写为秒），如`90`、`5m`、`1h`。
+// 包干：`5m`；加秒（Fischer）：`5m+3s`，每走一步加3秒；
+// 读秒：`10m/30s*3`，基本用时用完后每步有30秒，超过一次用掉一次读秒，3次用完判负。
+
+use std::time::{Duration, Instant};
+
+use crate::base::Player;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum TimeControl {
+    /// 包干，用完判负
+    SuddenDeath { total: Duration },
+    /// 每走一步加increment
+    Fischer { total: Duration, increment: Duration },
+    /// 基本用时用完后进入读秒
+    ByoYomi { main: Duration, period: Duration, periods: u8 },
+}
+
+fn parse_duration(s: &str) -> Option<Duration> {
+    let (num, unit) = match s.char_indices().last()? {
+        (i, c @ ('s' | 'm' | 'h')) => (&s[..i], c),
+        _ => (s, 's'),
+    };
+    let n: f64 = num.parse().ok().filter(|n: &f64| *n >= 0.0 && n.is_finite())?;
+    let scale = match unit {
+        'h' => 3600.0,
+        'm' => 60.0,
+        _ => 1.0,
+    };
+    Some(Duration::from_secs_f64(n * scale))
+}
+
+impl TimeControl {
+    pub fn from_name(s: &str) -> Option<TimeControl> {
+        if let Some((main, byo)) = s.split_once('/') {
+            let (period, periods) = byo.split_once('*').unwrap_or((byo, "1"));
+            return Some(TimeControl::ByoYomi {
+                main: parse_duration(main)?,
+                period: parse_duration(period)?,
+                periods: periods.parse().ok().filter(|n| *n > 0)?,
+            });
+        }
+        match s.split_once('+') {
+            Some((total, inc)) => Some(TimeControl::Fischer { total: parse_duration(total)?, increment: parse_duration(inc)? }),
+            None => Some(TimeControl::SuddenDeath { total: parse_duration(s)? }),
+        }
+    }
+
+    fn main_time(&self) -> Duration {
+        match *self {
+            TimeControl::SuddenDeath { total } | TimeControl::Fischer { total, .. } => total,
+            TimeControl::ByoYomi { main, .. } => main,
+        }
+    }
+}
+
+/// 双方的棋钟，只在走完一步时扣时间；时刻由调用方给出，便于测试
+#[derive(Clone, Debug)]
+pub struct Clock {
+    pub control: TimeControl,
+    /// 双方剩余的基本用时
+    remaining: [Duration; 2],
+    /// 双方剩余的读秒次数
+    periods: [u8; 2],
+    /// 当前一方开始思考的时刻
+    since: Instant,
+}
+
+/// 电脑每步大致按剩余基本用时的1/MOVES_LEFT安排
+const MOVES_LEFT: u32 = 30;
+
+impl Clock {
+    pub fn new(control: TimeControl, now: Instant) -> Self {
+        let periods = match control {
+            TimeControl::ByoYomi { periods, .. } => periods,
+            _ => 0,
+        };
+        Clock { control, remaining: [control.main_time(); 2], periods: [periods; 2], since: now }
+    }
+
+    /// 不走棋时重新开始计这一步，如电脑算完提示、编辑完局面
+    pub fn restart(&mut self, now: Instant) {
+        self.since = now;
+    }
+
+    fn period(&self) -> Duration {
+        match self.control {
+            TimeControl::ByoYomi { period, .. } => period,
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// 这一步最多能用多久，读秒全部用上也算
+    fn available(&self, player: Player) -> Duration {
+        let p = player.0 as usize;
+        self.remaining[p] + self.period() * self.periods[p] as u32
+    }
+
+    /// player在now时走完一步：扣掉用时，加秒或用掉读秒。超时返回false
+    pub fn press(&mut self, player: Player, now: Instant) -> bool {
+        let p = player.0 as usize;
+        let used = now.saturating_duration_since(self.since);
+        self.since = now;
+        if used > self.available(player) {
+            self.remaining[p] = Duration::ZERO;
+            self.periods[p] = 0;
+            return false;
+        }
+        match self.control {
+            TimeControl::SuddenDeath { .. } => self.remaining[p] -= used,
+            TimeControl::Fischer { increment, .. } => self.remaining[p] = self.remaining[p] - used + increment,
+            TimeControl::ByoYomi { period, .. } => {
+                if used <= self.remaining[p] {
+                    self.remaining[p] -= used;
+                } else {
+                    // 每超过一个读秒用掉一次，没超过的那次留着
+                    let over = used - self.remaining[p];
+                    self.remaining[p] = Duration::ZERO;
+                    self.periods[p] -= ((over.as_nanos() - 1) / period.as_nanos()) as u8;
+                }
+            },
+        }
+        true
+    }
+
+    /// 轮到player走，到now时是否已经超时
+    pub fn flagged(&self, player: Player, now: Instant) -> bool {
+        now.saturating_duration_since(self.since) > self.available(player)
+    }
+
+    /// 给电脑这一步的思考时间，留出余量
+    pub fn budget(&self, player: Player, now: Instant) -> Duration {
+        let p = player.0 as usize;
+        let left = self.available(player).saturating_sub(now.saturating_duration_since(self.since));
+        let plan = match self.control {
+            TimeControl::SuddenDeath { .. } => self.remaining[p] / MOVES_LEFT,
+            TimeControl::Fischer { increment, .. } => self.remaining[p] / MOVES_LEFT + increment * 3 / 4,
+            TimeControl::ByoYomi { period, .. } => (self.remaining[p] / MOVES_LEFT).max(period * 3 / 4),
+        };
+        plan.min(left * 3 / 4)
+    }
+
+    /// 显示用，如`4:59`、`0:00 (30s×3)`；轮到的一方扣掉正在用的时间
+    pub fn describe(&self, player: Player, running: bool, now: Instant) -> String {
+        let p = player.0 as usize;
+        let mut main = self.remaining[p];
+        let mut periods = self.periods[p];
+        if running {
+            let mut used = now.saturating_duration_since(self.since);
+            if used <= main {
+                main -= used;
+            } else {
+                used -= main;
+                main = Duration::ZERO;
+                // 读秒中显示这一次还剩多少
+                let period = self.period();
+                if !period.is_zero() {
+                    let lost = ((used.as_nanos().max(1) - 1) / period.as_nanos()) as u8;
+                    periods = periods.saturating_sub(lost);
+                    main = period * (lost as u32 + 1) - used;
+                }
+            }
+        }
+        let secs = main.as_secs();
+        let mut s = format!("{}:{:02}", secs / 60, secs % 60);
+        if let TimeControl::ByoYomi { period, .. } = self.control {
+            s += &format!(" ({}s×{})", period.as_secs(), periods);
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(n: u64) -> Duration {
+        Duration::from_secs(n)
+    }
+
+    #[test]
+    fn parses_controls() {
+        assert_eq!(TimeControl::from_name("90"), Some(TimeControl::SuddenDeath { total: secs(90) }));
+        assert_eq!(TimeControl::from_name("5m+3s"), Some(TimeControl::Fischer { total: secs(300), increment: secs(3) }));
+        assert_eq!(TimeControl::from_name("1h/30s*3"),
+            Some(TimeControl::ByoYomi { main: secs(3600), period: secs(30), periods: 3 }));
+        assert_eq!(TimeControl::from_name("0/10"),
+            Some(TimeControl::ByoYomi { main: secs(0), period: secs(10), periods: 1 }));
+        for bad in ["", "m", "5x", "-1", "5m+", "1m/30s*0"] {
+            assert!(TimeControl::from_name(bad).is_none(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn sudden_death_and_fischer() {
+        let t = Instant::now();
+        let mut c = Clock::new(TimeControl::SuddenDeath { total: secs(10) }, t);
+        assert!(c.press(Player(0), t + secs(4)));
+        assert!(!c.flagged(Player(1), t + secs(14)));
+        assert!(c.flagged(Player(1), t + secs(15)));
+        assert!(c.press(Player(1), t + secs(10)));
+        // 先手还剩6秒
+        assert!(!c.press(Player(0), t + secs(17)));
+
+        let mut c = Clock::new(TimeControl::Fischer { total: secs(10), increment: secs(5) }, t);
+        assert!(c.press(Player(0), t + secs(8)));
+        assert_eq!(c.describe(Player(0), false, t), "0:07");
+        assert_eq!(c.describe(Player(1), true, t + secs(9)), "0:09");
+    }
+
+    #[test]
+    fn byo_yomi_uses_up_periods() {
+        let t = Instant::now();
+        let mut c = Clock::new(TimeControl::ByoYomi { main: secs(10), period: secs(5), periods: 3 }, t);
+        // 用完基本用时，读秒内走完不扣次数
+        assert!(c.press(Player(0), t + secs(15)));
+        assert_eq!(c.describe(Player(0), false, t), "0:00 (5s×3)");
+        // 超过一次读秒
+        assert!(c.press(Player(1), t + secs(15)));
+        assert!(c.press(Player(0), t + secs(22)));
+        assert_eq!(c.describe(Player(0), false, t), "0:00 (5s×2)");
+        assert_eq!(c.describe(Player(1), true, t + secs(24)), "0:08 (5s×3)");
+        assert!(c.flagged(Player(1), t + secs(22 + 26)));
+        assert!(!c.press(Player(1), t + secs(22 + 26)));
+    }
+
+    #[test]
+    fn budget_leaves_margin() {
+        let t = Instant::now();
+        let c = Clock::new(TimeControl::SuddenDeath { total: secs(300) }, t);
+        assert_eq!(c.budget(Player(0), t), secs(10));
+        // 快没时间时不超过剩下的3/4
+        assert_eq!(c.budget(Player(0), t + secs(296)), secs(3));
+        let c = Clock::new(TimeControl::ByoYomi { main: secs(0), period: secs(20), periods: 1 }, t);
+        assert_eq!(c.budget(Player(1), t), secs(15));
+    }
+}
@@ -1,10 +1,13 @@
 pub mod tui;
+pub mod text;
 mod util;
 
 use std::fmt::Debug;
 
 use ::tui::text::{Span, Spans};
 
+use crate::{base::{Step, OutCome}, agent::{Action, Request}};
+
 pub trait Display {
     // type Output;
     fn to_array(&self) -> Vec<Vec<char>>;
@@ -14,10 +17,39 @@ pub trait Display {
     fn to_string(&self) -> String;
 }
 
-pub fn display<A: Debug>(array: A) {
-    println!("{:?}", array);
+/// 棋盘上的标记，不改变局面，只影响显示
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Mark {
+    /// 提示的候选步，数字为名次（从1开始）
+    Hint((u8, u8), u8),
+    /// 对方下一步就能赢的点
+    Threat((u8, u8)),
 }
 
 pub fn log(s: String) {
     unsafe { tui::tui_log(Spans::from(Span::raw(s))) };
 }
+
+/// 动作写成一行说明，走棋不说明
+pub fn describe_action<S: Step>(name: &str, action: &Action<S>) -> Option<String> {
+    let request = |r: &Request| match r {
+        Request::Undo => "悔棋",
+        Request::Draw => "和棋",
+    };
+    Some(match action {
+        Action::Step(_) => return None,
+        Action::Resign => format!("{}认输", name),
+        Action::Ask(r) => format!("{}请求{}，y同意，n拒绝", name, request(r)),
+        Action::Reply(r, ok) => format!("{}{}{}", name, if *ok { "同意" } else { "拒绝" }, request(r)),
+        Action::Chat(text) => format!("{}：{}", name, text),
+        Action::Leave(why) => format!("----{}离开：{}----", name, why),
+    })
+}
+
+pub fn describe_outcome(outcome: &OutCome) -> String {
+    match outcome {
+        OutCome::Draw => "平局".to_string(),
+        OutCome::Winer(y) => format!("Player {} Win !!!", y.0),
+        OutCome::Timeout(y) => format!("Player {} 超时，Player {} Win !!!", y.0, y.rev().0),
+    }
+}
@@ -0,0 +1,227 @@
+// 按行的文字界面：不用raw模式、备用屏幕和鼠标，适合简单终端、管道和脚本
+//
+// 棋盘按to_array画成ASCII，上方是列号y，左边是行号x，空格为`.`，提示为数字，对方能赢的点为`!`。
+// 轮到本地的人时读一行：
+//   x,y / x,y-x,y   落子 / 走棋，同record.rs
+//   r 认输  d 求和  u 悔棋  y/n 答复对方的请求  t <文字> 聊天
+//   h 提示  a 分析  p 显示局面  q 退出（读到输入结尾也退出）
+
+use std::{io::{BufRead, Write}, thread, time::{Duration, Instant}};
+
+use crate::{base::{Game, Step, Player, GameState}, ai::{self, AI, Level}, solver::Oracle, record,
+    position::{self, SetUp}, agent::{self, Agent, Action, Input}};
+
+use super::{tui::{TuiOptions, format_analysis}, Display, Mark, describe_action, describe_outcome};
+
+/// 没有人要输入、其它座位也没动作时（如等联网的对方）等一会
+const IDLE: Duration = Duration::from_millis(10);
+/// 分析显示几个候选
+const ANALYSIS_TOP: usize = 5;
+
+const HELP: &str = "输入坐标x,y落子（走棋为x,y-x,y），r认输，d求和，u悔棋，y/n答复，t <文字>聊天，h提示，a分析，p局面，q退出";
+
+/// 棋盘画成几行文字
+pub fn render<M: Display>(board: &M, marks: &[Mark]) -> String {
+    let (rows, cols) = board.size();
+    let arr = board.to_array();
+    let mut s = "  ".to_string();
+    for y in 0..cols {
+        s += &format!("{:>3}", y);
+    }
+    for x in 0..rows {
+        s += &format!("\n{:>2}", x);
+        for y in 0..cols {
+            let mut ch = arr[x as usize][y as usize];
+            // 标记只画在空格上
+            if ch == ' ' {
+                ch = '.';
+                for m in marks {
+                    match *m {
+                        Mark::Hint(pos, n) if pos == (x, y) => ch = char::from_digit(n as u32, 10).unwrap_or('?'),
+                        Mark::Threat(pos) if pos == (x, y) => ch = '!',
+                        _ => (),
+                    }
+                }
+            }
+            s += &format!("{:>3}", ch);
+        }
+    }
+    s
+}
+
+/// 一行输入变成给人的输入，不是走棋与动作时返回None
+fn parse_input(line: &str) -> Option<Vec<Input>> {
+    if let Some(text) = line.strip_prefix("t ") {
+        return Some(vec![Input::Text(text.trim().to_string())]);
+    }
+    if let [c @ ('r' | 'd' | 'u' | 'y' | 'n')] = line.chars().collect::<Vec<_>>()[..] {
+        return Some(vec![Input::Key(c)]);
+    }
+    // 走棋类依次点起点和终点
+    line.split('-').map(|p| record::parse_pos(p).map(Input::Click)).collect()
+}
+
+/// 座位agents[i]执Player(i)，从input读本地的人的输入，局面与消息写到out
+pub fn text_main<B, S>(game: &mut Game<B, S>, mut agents: [Box<dyn Agent<B, S> + '_>; 2],
+    oracle: Option<&dyn Oracle<B, S>>, opts: TuiOptions, input: impl BufRead, mut out: impl Write) where
+    B: SetUp<S> + Display + AI<S>,
+    S: Step
+{
+    let mut lines = input.lines();
+    let mut say = |text: String| {
+        let _ = writeln!(out, "{}", text);
+        let _ = out.flush();
+    };
+    let value = |game: &Game<B, S>| oracle.and_then(|o| o.value(&game.board, game.curr_player))
+        .map(|v| format!("理论值：{}", v.describe(game.curr_player)));
+    // 等待答复的悔棋或求和请求：提出方，内容
+    let mut request = None;
+    // 上次画棋盘时的步数
+    let mut shown = None;
+    let mut marks = vec![];
+    if let Some(c) = &mut game.clock {
+        c.restart(Instant::now());
+    }
+    say(format!("0：{}  x：{}", agents[0].name(), agents[1].name()));
+    say(HELP.to_string());
+    loop {
+        if let GameState::Over(outcome) = &game.state {
+            say(render(&game.board, &[]));
+            say("----游戏结束----".to_string());
+            say(describe_outcome(outcome));
+            break;
+        }
+        if game.check_time() {
+            continue;
+        }
+        let curr = game.curr_player.0 as usize;
+        // 本地的人要输入：轮到他，或者对方的请求等他答复
+        let waiting = (0..2).find(|&i| agents[i].is_local_human()
+            && (i == curr || request.is_some_and(|(from, _): (Player, _)| from.0 as usize != i)));
+
+        let mut inputs = vec![];
+        if let Some(i) = waiting {
+            if shown != Some(game.hist_steps.len()) {
+                shown = Some(game.hist_steps.len());
+                marks.clear();
+                if opts.threat_warning && i == curr {
+                    marks = ai::threats(game).iter().map(|s| Mark::Threat(s.to_pos())).collect();
+                }
+                say(render(&game.board, &marks));
+                if let Some(v) = value(game) {
+                    say(v);
+                }
+            }
+            say(format!("{}>", i));
+            let Some(Ok(line)) = lines.next() else {
+                break;
+            };
+            match line.trim() {
+                "" => continue,
+                "q" => break,
+                "?" | "help" => say(HELP.to_string()),
+                "p" => say(format!("局面：{}", position::to_string(&game.board, game.curr_player))),
+                cmd @ ("h" | "a") => {
+                    let time = if cmd == "h" { opts.hint_time } else { opts.hint_time * 2 };
+                    let level = Level { time: Some(time), ..Level::FULL }.with_threads(opts.threads);
+                    let analysis = ai::analyze(&game.board, game.curr_player, &level, ai::game_ctx(game));
+                    if cmd == "h" {
+                        let top: Vec<_> = analysis.moves.iter().take(opts.hint_top.min(9)).collect();
+                        marks = top.iter().enumerate().map(|(i, m)| Mark::Hint(m.step.to_pos(), i as u8 + 1)).collect();
+                        say(render(&game.board, &marks));
+                        let text: Vec<_> = top.iter().map(|m| record::step_to_string(&m.step)).collect();
+                        say(format!("提示：{}", text.join(" ")));
+                    } else {
+                        format_analysis(&analysis, ANALYSIS_TOP).into_iter().for_each(&mut say);
+                    }
+                },
+                line => match parse_input(line) {
+                    Some(v) => inputs = v,
+                    None => say(format!("看不懂：{}（?看帮助）", line)),
+                },
+            }
+            if inputs.is_empty() {
+                continue;
+            }
+        }
+
+        let steps = game.hist_steps.len();
+        let mut acted = false;
+        for i in [curr, 1 - curr] {
+            let me = Player(i as u8);
+            let polls = if Some(i) == waiting { std::mem::take(&mut inputs) } else { vec![Input::None] };
+            for input in polls {
+                let asked = request.filter(|(from, _)| *from != me).map(|(_, r)| r);
+                let Some(action) = agents[i].poll(game, me, &input, asked) else {
+                    continue;
+                };
+                acted = true;
+                if agent::apply(game, me, &action, &mut request) {
+                    match &action {
+                        Action::Step(s) => say(format!("{}：{}", i, record::step_to_string(s))),
+                        a => say(describe_action(&agents[i].name(), a).unwrap_or_default()),
+                    }
+                    agents[1 - i].observe(game, &action);
+                } else if let Action::Step(s) = &action {
+                    say(format!("{}的走法不合法：{}", agents[i].name(), record::step_to_string(s)));
+                }
+            }
+        }
+        if game.hist_steps.len() < steps {
+            shown = None;
+        }
+        if !acted && waiting.is_none() {
+            thread::sleep(IDLE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{base::{Board, GameType}, agent::{Human, Computer}, tic_tac_toe::{TTTBoard, TTTStep}};
+
+    fn play(script: &str) -> String {
+        let mut game = Game::new(TTTBoard::new(), GameType::Put);
+        let agents: [Box<dyn Agent<TTTBoard, TTTStep>>; 2] = [Box::new(Human::new()), Box::new(Computer::new(Level::FULL, None))];
+        let mut out = vec![];
+        let opts = TuiOptions { threat_warning: false, ..TuiOptions::default() };
+        text_main(&mut game, agents, None, opts, script.as_bytes(), &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn renders_board_and_marks() {
+        let mut board = TTTBoard::new();
+        board.put(TTTStep::new_put_step((1, 1), Player(0)));
+        board.put(TTTStep::new_put_step((0, 2), Player(1)));
+        let text = render(&board, &[Mark::Hint((2, 0), 1), Mark::Threat((1, 1))]);
+        assert_eq!(text, "    0  1  2\n 0  .  .  x\n 1  .  o  .\n 2  1  .  .");
+    }
+
+    #[test]
+    fn reads_typed_moves() {
+        assert_eq!(parse_input("1,2"), Some(vec![Input::Click((1, 2))]));
+        assert_eq!(parse_input("0,0-1,0"), Some(vec![Input::Click((0, 0)), Input::Click((1, 0))]));
+        assert_eq!(parse_input("r"), Some(vec![Input::Key('r')]));
+        assert_eq!(parse_input("t good game"), Some(vec![Input::Text("good game".to_string())]));
+        assert_eq!(parse_input("1;2"), None);
+        assert_eq!(parse_input("rr"), None);
+    }
+
+    #[test]
+    fn plays_from_script() {
+        // 中心被占后电脑应在角上；9,9出界，1,1已有子
+        let out = play("1,1\n9,9\n1,1\nfoo\nq\n");
+        assert!(out.contains("0：1,1"), "{}", out);
+        assert!(out.contains("1：0,0") || out.contains("1：0,2") || out.contains("1：2,0") || out.contains("1：2,2"), "{}", out);
+        assert_eq!(out.matches("人的走法不合法").count(), 2, "{}", out);
+        assert!(out.contains("看不懂：foo"), "{}", out);
+        assert!(!out.contains("游戏结束"));
+
+        // 认输后游戏结束；输入结尾也会退出
+        let out = play("r\n");
+        assert!(out.contains("人认输") && out.contains("Player 1 Win"), "{}", out);
+        assert!(!play("1,1\n").contains("游戏结束"));
+    }
+}
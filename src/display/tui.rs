@@ -1,11 +1,11 @@
-use std::{io::{self, Stdout}, time::Duration, collections::VecDeque};
+use std::{io::{self, Stdout}, time::{Duration, Instant}, collections::VecDeque};
 
-use crossterm::{terminal::{enable_raw_mode, EnterAlternateScreen, disable_raw_mode, LeaveAlternateScreen}, execute, event::{EnableMouseCapture, poll, Event, MouseEventKind, read, MouseButton, DisableMouseCapture}};
+use crossterm::{terminal::{enable_raw_mode, EnterAlternateScreen, disable_raw_mode, LeaveAlternateScreen}, execute, event::{EnableMouseCapture, poll, Event, MouseEventKind, read, MouseButton, DisableMouseCapture, KeyCode}};
 use tui::{Terminal, backend::CrosstermBackend, widgets::{Paragraph, Block, Borders, Wrap}, style::{Color, Style}, layout::{Alignment, Layout, Direction, Constraint}, text::{Spans, Span}};
 
-use crate::{base::{Game, Step, Board, Player, self, Role, GameType}, ai::{self, AI}};
+use crate::{base::{Game, Step, Board, Player, self}, ai::{self, AI, Level, Analysis}, solver::Oracle, record, position::{self, SetUp}, puzzle::{PuzzleSet, Attempt, Progress, Status}, agent::{self, Agent, Action, Input}};
 
-use super::{util, Display, log};
+use super::{util, Display, Mark, log};
 
 static mut TEXT: Vec<Spans> = Vec::new();
 
@@ -21,6 +21,7 @@ pub unsafe fn tui_log(spans: Spans<'static>) {
 pub enum TuiEvent {
     None,
     GetPos((u16, u16)),
+    Key(char),
     Exit,
 }
 
@@ -33,7 +34,9 @@ pub fn tui_init() -> Result<Terminal<CrosstermBackend<Stdout>>, io::Error> {
     Ok(Terminal::new(backend)?)
 }
 
-pub fn tui_draw<M: Display>(terminal: &mut Terminal<CrosstermBackend<Stdout>>, maps: Vec<&M>) {
+/// title显示在棋盘框上（如棋钟），info显示在右下角的分析区，为空时不显示
+pub fn tui_draw<M: Display>(terminal: &mut Terminal<CrosstermBackend<Stdout>>, maps: Vec<&M>,
+    marks: &[Mark], info: &[String], title: &str) {
     let _ = terminal.draw(|f| {
         let chunks = Layout::default()
             .direction(Direction::Horizontal)
@@ -47,8 +50,8 @@ pub fn tui_draw<M: Display>(terminal: &mut Terminal<CrosstermBackend<Stdout>>, m
             .split(f.size());
 
         {
-            let p = Paragraph::new(util::generate_map(maps[0]))
-                .block(Block::default().borders(Borders::ALL))
+            let p = Paragraph::new(util::generate_map(maps[0], marks))
+                .block(Block::default().title(title.to_string()).borders(Borders::ALL))
                 .style(Style::default().fg(Color::White))//.bg(Color::Black))
                 .alignment(Alignment::Left)
                 .wrap(Wrap { trim: false });
@@ -56,6 +59,15 @@ pub fn tui_draw<M: Display>(terminal: &mut Terminal<CrosstermBackend<Stdout>>, m
             f.render_widget(p, chunks[0]);
         }
 
+        let right = if info.is_empty() {
+            vec![chunks[1]]
+        } else {
+            Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+                .split(chunks[1])
+        };
+
         {
             let p = Paragraph::new(unsafe { TEXT.clone() })
                 .block(Block::default().borders(Borders::ALL))
@@ -63,14 +75,34 @@ pub fn tui_draw<M: Display>(terminal: &mut Terminal<CrosstermBackend<Stdout>>, m
                 .alignment(Alignment::Left)
                 .wrap(Wrap { trim: false });
 
-            f.render_widget(p, chunks[1]);
+            f.render_widget(p, right[0]);
+        }
+
+        if !info.is_empty() {
+            let text: Vec<Spans> = info.iter().map(|l| Spans::from(Span::raw(l.clone()))).collect();
+            let p = Paragraph::new(text)
+                .block(Block::default().title("分析").borders(Borders::ALL))
+                .style(Style::default().fg(Color::White))
+                .alignment(Alignment::Left)
+                .wrap(Wrap { trim: false });
+
+            f.render_widget(p, right[1]);
         }
     });
 }
 
 pub fn tui_get_event() -> TuiEvent {
     if poll(Duration::from_millis(100)).unwrap() {
-        if let Event::Mouse(mouse) = read().unwrap() {
+        let event = read().unwrap();
+        if let Event::Key(key) = event {
+            match key.code {
+                KeyCode::Char(c) => return TuiEvent::Key(c),
+                KeyCode::Enter => return TuiEvent::Key('\n'),
+                KeyCode::Backspace => return TuiEvent::Key('\x08'),
+                _ => (),
+            }
+        }
+        if let Event::Mouse(mouse) = event {
             if mouse.kind == MouseEventKind::Down(MouseButton::Left) {
                 let pos = util::get_pos(mouse.column, mouse.row);
                 if pos != None {
@@ -96,59 +128,332 @@ pub fn tui_exit(terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> Result<(),
     Ok(())
 }
 
-pub fn tui_main<B, S>(game: &mut Game<B, S>) where
-    B: Board<S> + Display + AI<S>,
+/// 显示理论值（有表时）
+fn log_value<B, S: Step>(game: &Game<B, S>, oracle: Option<&dyn Oracle<B, S>>) where
+    B: Board<S>
+{
+    if let Some(v) = oracle.and_then(|o| o.value(&game.board, game.curr_player)) {
+        log(format!("理论值：{}", v.describe(game.curr_player)));
+    }
+}
+
+/// 分析模式的思考时间
+const ANALYSIS_TIME: Duration = Duration::from_secs(3);
+/// 分析模式显示几个候选
+const ANALYSIS_TOP: usize = 5;
+
+/// 界面选项
+#[derive(Copy, Clone, Debug)]
+pub struct TuiOptions {
+    /// 提示的思考时间
+    pub hint_time: Duration,
+    /// 提示几个候选（最多9个）
+    pub hint_top: usize,
+    /// 轮到人走时，标出对方下一步就能赢的点
+    pub threat_warning: bool,
+    /// 分析与提示的搜索线程数
+    pub threads: u8,
+}
+
+impl Default for TuiOptions {
+    fn default() -> Self {
+        TuiOptions { hint_time: Duration::from_secs(2), hint_top: 3, threat_warning: true, threads: 1 }
+    }
+}
+
+/// 分析结果整理成几行文字
+pub fn format_analysis<S: Step>(analysis: &Analysis<S>, top: usize) -> Vec<String> {
+    let mut v = vec![format!("深度 {}  节点 {}", analysis.depth, analysis.nodes)];
+    for (i, m) in analysis.moves.iter().take(top).enumerate() {
+        v.push(format!("{}. {}  {}", i + 1, record::step_to_string(&m.step), m.score));
+        v.push(format!("   {}", record::game_to_string(&m.pv)));
+    }
+    v
+}
+
+/// 对当前局面做一次限时分析
+fn analyze_game<B: AI<S>, S: Step>(game: &Game<B, S>, opts: &TuiOptions) -> Vec<String> {
+    let level = Level { time: Some(ANALYSIS_TIME), ..Level::FULL }.with_threads(opts.threads);
+    let analysis = ai::analyze(&game.board, game.curr_player, &level, ai::game_ctx(game));
+    format_analysis(&analysis, ANALYSIS_TOP)
+}
+
+/// 为当前行棋方找提示，标出前几个候选
+fn hint_marks<B: AI<S>, S: Step>(game: &Game<B, S>, opts: &TuiOptions) -> Vec<Mark> {
+    let level = Level { time: Some(opts.hint_time), ..Level::FULL }.with_threads(opts.threads);
+    let analysis = ai::analyze(&game.board, game.curr_player, &level, ai::game_ctx(game));
+    let top: Vec<_> = analysis.moves.iter().take(opts.hint_top.min(9)).collect();
+    let text: Vec<_> = top.iter().map(|m| record::step_to_string(&m.step)).collect();
+    log(format!("提示：{}", text.join(" ")));
+    top.iter().enumerate().map(|(i, m)| Mark::Hint(m.step.to_pos(), i as u8 + 1)).collect()
+}
+
+fn threat_marks<B: AI<S> + SetUp<S>, S: Step>(game: &Game<B, S>) -> Vec<Mark> {
+    ai::threats(game).iter().map(|s| Mark::Threat(s.to_pos())).collect()
+}
+
+/// 双方棋钟，轮到的一方前面加`>`，不计时为空
+fn clock_title<B: Board<S>, S: Step>(game: &Game<B, S>) -> String {
+    let Some(clock) = &game.clock else {
+        return String::new();
+    };
+    let now = Instant::now();
+    let running = game.state == base::GameState::Running;
+    (0..2).map(|i| {
+        let p = Player(i);
+        let turn = running && p == game.curr_player;
+        format!("{}{}: {}", if turn { ">" } else { " " }, i, clock.describe(p, turn, now))
+    }).collect::<Vec<_>>().join("  ")
+}
+
+/// 编辑模式下点击格子：空、先手、后手循环
+fn cycle_cell<B: SetUp<S>, S: Step>(board: &B, (x, y): (u16, u16)) -> B {
+    let side = board.side() as usize;
+    let mut cells = board.cells();
+    if let Some(c) = cells.get_mut(x as usize * side + y as usize).filter(|_| (y as usize) < side) {
+        *c = match c {
+            None => Some(Player(0)),
+            Some(Player(0)) => Some(Player(1)),
+            Some(_) => None,
+        };
+    }
+    board.set_up(&cells)
+}
+
+/// 座位agents[i]执Player(i)。oracle为完美走法表，有则显示当前局面的理论值
+pub fn tui_main<B, S>(game: &mut Game<B, S>, mut agents: [Box<dyn Agent<B, S> + '_>; 2],
+    oracle: Option<&dyn Oracle<B, S>>, opts: TuiOptions) where
+    B: SetUp<S> + Display + AI<S>,
     S: Step + std::fmt::Debug
 {
     let mut tem = tui_init().unwrap();
-    let mut last_click_pos = None;
     let mut over_flag = false;
+    // 分析模式：按a切换，局面变化后重新分析
+    let mut analysis_on = false;
+    let mut analysed_at = None;
+    let mut info = vec![];
+    // 提示与威胁标记，局面变化后清除
+    let mut hints = vec![];
+    let mut marked_at = None;
+    let mut threats = vec![];
+    // 编辑模式：正在摆的局面与行棋方，按e完成后从这里重新开始
+    let mut editing: Option<(B, Player)> = None;
+    // 正在输入的聊天内容
+    let mut chat: Option<String> = None;
+    // 等待答复的悔棋或求和请求：提出方，内容
+    let mut request = None;
+    // 求解、载入等准备工作不算用时
+    if let Some(c) = &mut game.clock {
+        c.restart(Instant::now());
+    }
+    log(format!("0：{}  x：{}", agents[0].name(), agents[1].name()));
+    log_value(game, oracle);
+    if game.board.cells() != B::new().cells() {
+        log(format!("局面：{}", position::to_string(&game.board, game.curr_player)));
+    }
+    log("按a切换分析模式，h提示，p显示局面，e编辑局面，q退出".to_string());
+    log("r认输，d求和，u悔棋，t聊天".to_string());
     loop {
-        tui_draw(&mut tem, vec![&game.board]);
-
-        let event = tui_get_event();
-        if let TuiEvent::Exit = event {
-            break;
-        }
-
-        if !over_flag {
-            if game.players[game.curr_player.0 as usize] == Role::Com {
-                game.step(ai::get_next_best_step(&game.board, game.curr_player).unwrap())
-                .then(||{
-                    log(game.board.to_string());
-                });
-            } else {
-                if let TuiEvent::GetPos((x, y)) = event {
-                    if game.game_type == GameType::Put {
-                        let step = S::new_put_step((x as u8, y as u8), game.curr_player);
-                        if game.state == base::GameState::Running {
-                            game.step(step);
-                            log(game.board.to_string());
-                        }
-                    } else {
-                        last_click_pos = match last_click_pos {
-                            None => Some((x as u8, y as u8)),
-                            Some((fx, fy)) => {
-                                if game.state == base::GameState::Running {
-                                    game.step(S::new_move_step((fx, fy), (x as u8, y as u8), game.curr_player));
-                                }
-                                None
-                            }
-                        }
+        if let Some((board, next)) = &editing {
+            tui_draw(&mut tem, vec![board], &[], &info, "编辑");
+            match tui_get_event() {
+                TuiEvent::Exit | TuiEvent::Key('q') => break,
+                TuiEvent::GetPos(pos) => editing = Some((cycle_cell(board, pos), *next)),
+                TuiEvent::Key('s') => {
+                    log(format!("轮到{}走", next.rev().0));
+                    editing = Some((board.clone(), next.rev()));
+                },
+                TuiEvent::Key('c') => editing = Some((board.set_up(&vec![None; board.cells().len()]), *next)),
+                TuiEvent::Key('p') => log(format!("局面：{}", position::to_string(board, *next))),
+                TuiEvent::Key('e') => {
+                    let (board, next) = editing.take().unwrap();
+                    log(format!("局面：{}", position::to_string(&board, next)));
+                    game.reset_to(board, next);
+                    over_flag = false;
+                    analysed_at = None;
+                    marked_at = None;
+                    request = None;
+                    log_value(game, oracle);
+                },
+                _ => (),
+            }
+            continue;
+        }
+
+        // 聊天输入完成后作为一次输入交给本地的人
+        let mut input = Input::None;
+        if let Some(text) = &mut chat {
+            tui_draw(&mut tem, vec![&game.board], &[], &info, &format!("说：{}", text));
+            match tui_get_event() {
+                TuiEvent::Exit => chat = None,
+                TuiEvent::Key('\n') => input = Input::Text(chat.take().unwrap()),
+                TuiEvent::Key('\x08') => {
+                    text.pop();
+                },
+                TuiEvent::Key(c) => text.push(c),
+                _ => (),
+            }
+            if input == Input::None {
+                continue;
+            }
+        }
+
+        let curr = game.curr_player.0 as usize;
+        if marked_at != Some(game.hist_steps.len()) {
+            hints.clear();
+            threats.clear();
+            if opts.threat_warning && agents[curr].is_local_human() && !over_flag {
+                threats = threat_marks(game);
+            }
+            marked_at = Some(game.hist_steps.len());
+        }
+        let marks: Vec<Mark> = hints.iter().chain(threats.iter()).copied().collect();
+
+        if analysis_on && analysed_at != Some(game.hist_steps.len()) && !over_flag {
+            info = vec!["分析中...".to_string()];
+            tui_draw(&mut tem, vec![&game.board], &marks, &info, &clock_title(game));
+            info = analyze_game(game, &opts);
+            analysed_at = Some(game.hist_steps.len());
+        }
+        tui_draw(&mut tem, vec![&game.board], &marks, &info, &clock_title(game));
+
+        if input == Input::None {
+            match tui_get_event() {
+                TuiEvent::Exit | TuiEvent::Key('q') => break,
+                TuiEvent::Key('a') => {
+                    analysis_on = !analysis_on;
+                    analysed_at = None;
+                    info.clear();
+                },
+                TuiEvent::Key('p') => {
+                    log(format!("局面：{}", position::to_string(&game.board, game.curr_player)));
+                },
+                TuiEvent::Key('e') if !agents.iter().all(|a| a.can_edit()) => log("和对方对局时不能编辑局面".to_string()),
+                TuiEvent::Key('e') => {
+                    log("编辑：点击格子换子（空、0、x循环），s换行棋方，c清空，e完成".to_string());
+                    editing = Some((game.board.clone(), game.curr_player));
+                    continue;
+                },
+                TuiEvent::Key('h') if !over_flag && agents[curr].is_local_human() => {
+                    log("思考中...".to_string());
+                    tui_draw(&mut tem, vec![&game.board], &marks, &info, &clock_title(game));
+                    hints = hint_marks(game, &opts);
+                },
+                TuiEvent::Key('t') => chat = Some(String::new()),
+                TuiEvent::GetPos((x, y)) => input = Input::Click((x as u8, y as u8)),
+                TuiEvent::Key(c) => input = Input::Key(c),
+                _ => (),
+            }
+        }
+
+        let steps = game.hist_steps.len();
+        let running = game.state == base::GameState::Running;
+        if running && game.check_time() {
+            // 超时判负，下面按游戏结束处理
+        } else {
+            // 输入交给轮到的本地的人，不是人时交给另一边本地的人
+            let focus = if agents[curr].is_local_human() { curr } else { 1 - curr };
+            for i in [curr, 1 - curr] {
+                let me = Player(i as u8);
+                let asked = request.filter(|(from, _)| *from != me).map(|(_, r)| r);
+                let input = if i == focus { &input } else { &Input::None };
+                let Some(action) = agents[i].poll(game, me, input, asked) else {
+                    continue;
+                };
+                if agent::apply(game, me, &action, &mut request) {
+                    if let Some(text) = super::describe_action(&agents[i].name(), &action) {
+                        log(text);
                     }
+                    agents[1 - i].observe(game, &action);
+                } else if let (Action::Step(s), false) = (&action, agents[i].is_local_human()) {
+                    // 人点错格子很常见，不提示
+                    log(format!("{}的走法不合法：{}", agents[i].name(), record::step_to_string(s)));
                 }
             }
+        }
 
-            // over
-            if let base::GameState::Over(x) = &game.state {
-                log("----游戏结束----".to_string());
-                match x {
-                    base::OutCome::Draw => log("平局".to_string()),
-                    base::OutCome::Winer(y) => log(format!("Player {} Win !!!", y.0))
-                };
-                over_flag = true;
+        if game.hist_steps.len() != steps {
+            log(game.board.to_string());
+            if game.state == base::GameState::Running {
+                log_value(game, oracle);
+            }
+        }
+        // 悔棋后局面回到对局中
+        if over_flag && game.state == base::GameState::Running {
+            over_flag = false;
+        }
+        if let (false, base::GameState::Over(x)) = (over_flag, &game.state) {
+            log("----游戏结束----".to_string());
+            log(super::describe_outcome(x));
+            over_flag = true;
+        }
+    }
+    let _ = tui_exit(&mut tem);
+}
+
+/// 做题：点击落子，电脑按冲四求解应对。n下一题，b上一题，r重做，s看答案（记为失败），q退出。
+/// 每题做完后把状态写回path
+pub fn tui_puzzle(set: &mut PuzzleSet, path: &str) {
+    if set.puzzles.is_empty() {
+        return;
+    }
+    let mut tem = tui_init().unwrap();
+    let total = set.puzzles.len();
+    let mut index = set.puzzles.iter().position(|p| p.status != Status::Solved).unwrap_or(0);
+    let mut attempt = None;
+    let mut done = false;
+    let mut marks = vec![];
+    log("点击落子，n下一题，b上一题，r重做，s看答案，q退出".to_string());
+    loop {
+        let puzzle = &mut set.puzzles[index];
+        let a = attempt.get_or_insert_with(|| {
+            log(format!("第{}/{}题：{}", index + 1, total, puzzle.goal()));
+            done = false;
+            marks.clear();
+            Attempt::new(puzzle)
+        });
+        tui_draw(&mut tem, vec![&a.board], &marks, &[], &puzzle.goal());
+
+        // 这一题做完时的状态与说明
+        let mut result = None;
+        match tui_get_event() {
+            TuiEvent::Exit | TuiEvent::Key('q') => break,
+            TuiEvent::Key('n') => {
+                index = (index + 1) % total;
+                attempt = None;
+            },
+            TuiEvent::Key('b') => {
+                index = (index + total - 1) % total;
+                attempt = None;
+            },
+            TuiEvent::Key('r') => attempt = None,
+            TuiEvent::Key('s') => {
+                let attacker = a.attacker;
+                marks = puzzle.solution.iter().filter(|s| s.who() == attacker).enumerate()
+                    .map(|(i, s)| Mark::Hint(s.to_pos(), i as u8 + 1)).collect();
+                result = Some((Status::Failed, format!("答案：{}", record::game_to_string(&puzzle.solution))));
+            },
+            TuiEvent::GetPos((x, y)) if !done => match a.play((x as u8, y as u8)) {
+                None => (),
+                Some(Progress::Continue(reply)) => log(format!("对方应{}，还能冲四{}步",
+                    record::step_to_string(&reply), a.fours_left)),
+                Some(Progress::Solved) => result = Some((Status::Solved, "----正确----".to_string())),
+                Some(Progress::Failed(why)) => result = Some((Status::Failed, format!("----失败：{}----", why))),
+            },
+            _ => (),
+        }
+        if let Some((status, text)) = result {
+            log(text);
+            // 做对过的题不因为后来看答案变成失败
+            if puzzle.status != Status::Solved {
+                puzzle.status = status;
+            }
+            done = true;
+            if set.save(path).is_err() {
+                log(format!("保存{}失败", path));
             }
         }
     }
     let _ = tui_exit(&mut tem);
+    println!("{}题，做对{}，失败{}", total, set.count(Status::Solved), set.count(Status::Failed));
 }
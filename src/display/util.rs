@@ -1,6 +1,6 @@
 use tui::{text::{Text, Span, Spans}, style::{Style, Modifier}};
 
-use super::Display;
+use super::{Display, Mark};
 
 // use crate::board::{Pos, TTTBoard};
 
@@ -32,7 +32,7 @@ pub fn generate_empty_board(size: u8) -> Vec<Spans<'static>> {
     text
 }
 
-pub fn generate_map<M: Display>(a: &M) -> Vec<Spans<'static>> {
+pub fn generate_map<M: Display>(a: &M, marks: &[Mark]) -> Vec<Spans<'static>> {
     let size = a.size();
     let arr = a.to_array();
     // let data = Span::raw("x");
@@ -56,6 +56,20 @@ pub fn generate_map<M: Display>(a: &M) -> Vec<Spans<'static>> {
             ch.style = Style::default()
                 // .fg(tui::style::Color::Red)
                 .add_modifier(Modifier::BOLD);
+            // 标记只画在空格上
+            if arr[i as usize][j as usize] == ' ' {
+                for m in marks {
+                    match *m {
+                        Mark::Hint(pos, n) if pos == (i, j) => {
+                            ch = Span::styled(n.to_string(), ch.style.fg(tui::style::Color::LightGreen));
+                        },
+                        Mark::Threat(pos) if pos == (i, j) => {
+                            ch = Span::styled("!", ch.style.fg(tui::style::Color::LightRed));
+                        },
+                        _ => (),
+                    }
+                }
+            }
             line.push(ch);
             if j != size.1-1 {
                 line.push(vl.clone());
@@ -1,10 +1,11 @@
 use crate::base::*;
 use crate::ai::*;
 use crate::display::*;
+use crate::position::SetUp;
 
 const SIZE:u8 = 4;
 
-#[derive(PartialEq, Copy, Clone, Debug)]
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
 enum GoTPiece {
     P(Player),
     None,
@@ -38,6 +39,14 @@ impl Step for GoTStep {
     fn new_move_step(from: (u8, u8), to: (u8, u8), p: Player) -> Self {
         GoTStep { who: p, from: GoTPos(from.0, from.1), to: GoTPos(to.0, to.1) }
     }
+
+    fn to_pos(&self) -> (u8, u8) {
+        (self.to.0, self.to.1)
+    }
+
+    fn origin_pos(&self) -> Option<(u8, u8)> {
+        Some((self.from.0, self.from.1))
+    }
 }
 
 // impl MoveStep for GoTStep {
@@ -67,22 +76,114 @@ impl Board<GoTStep> for GoTBoard {
 
     fn over(&self) -> GameState {
         if self.pieces_num[0] <= 1 {
-            GameState::Over(OutCome::Winer(Player(0)))
-        } else if self.pieces_num[1] <= 1 {
             GameState::Over(OutCome::Winer(Player(1)))
+        } else if self.pieces_num[1] <= 1 {
+            GameState::Over(OutCome::Winer(Player(0)))
         } else {
             GameState::Running
         }
     }
+
+    fn key(&self) -> u64 {
+        hash_key(&self.pieces)
+    }
+
+    fn pieces_count(&self) -> u16 {
+        (self.pieces_num[0] + self.pieces_num[1]) as u16
+    }
+}
+
+impl GoTBoard {
+    const WIN: i32 = 1000;
+    const PIECE: i32 = 100;
 }
 
 impl AI<GoTStep> for GoTBoard {
-    fn score(&self, player: Player) -> i16 {
-        todo!()
+    /// 暂时只按棋子数量计分
+    fn score(&self, player: Player) -> i32 {
+        match self.over() {
+            GameState::Over(OutCome::Winer(p)) => {
+                if p == player {Self::WIN} else {-Self::WIN}
+            },
+            GameState::Over(OutCome::Timeout(p)) => {
+                if p == player {-Self::WIN} else {Self::WIN}
+            },
+            GameState::Over(OutCome::Draw) => 0,
+            GameState::Running => {
+                let me = self.pieces_num[player.0 as usize] as i32;
+                let ot = self.pieces_num[player.rev().0 as usize] as i32;
+                (me - ot) * Self::PIECE
+            }
+        }
     }
 
-    fn get_possible_steps(&self, player: Player, deep: u8) -> Vec<GoTStep> {
-        todo!()
+    fn side_scores(&self, player: Player) -> (i32, i32) {
+        let me = self.pieces_num[player.0 as usize] as i32;
+        let ot = self.pieces_num[player.rev().0 as usize] as i32;
+        (me * Self::PIECE, ot * Self::PIECE)
+    }
+
+    fn get_possible_steps(&self, player: Player, _deep: u8) -> Vec<GoTStep> {
+        let mut v = vec![];
+        for i in 0..SIZE {
+            for j in 0..SIZE {
+                let from = GoTPos(i, j);
+                if self.get_piece(&from) != GoTPiece::P(player) {
+                    continue;
+                }
+                for dir in [Dir::Up, Dir::Down, Dir::Left, Dir::Right] {
+                    if let Some(to) = from.near_by(&dir, 1) {
+                        if self.get_piece(&to).is_none() {
+                            v.push(GoTStep { who: player, from, to });
+                        }
+                    }
+                }
+            }
+        }
+        v
+    }
+}
+
+impl Symmetry<GoTStep> for GoTBoard {
+    fn side(&self) -> u8 {
+        SIZE
+    }
+
+    fn cells(&self) -> Vec<Option<Player>> {
+        self.pieces.iter().flatten().map(|p| match p {
+            GoTPiece::P(x) => Some(*x),
+            GoTPiece::None => None,
+        }).collect()
+    }
+
+    fn transform(&self, t: Sym) -> Self {
+        let mut b = self.clone();
+        for i in 0..SIZE {
+            for j in 0..SIZE {
+                let (x, y) = t.pos(SIZE, (i, j));
+                b.pieces[x as usize][y as usize] = self.get_piece(&GoTPos(i, j));
+            }
+        }
+        b
+    }
+
+    fn transform_step(&self, step: &GoTStep, t: Sym) -> GoTStep {
+        let from = t.pos(SIZE, (step.from.0, step.from.1));
+        let to = t.pos(SIZE, (step.to.0, step.to.1));
+        GoTStep::new_move_step(from, to, step.who)
+    }
+}
+
+impl SetUp<GoTStep> for GoTBoard {
+    fn set_up(&self, cells: &[Option<Player>]) -> Self {
+        let mut b = GoTBoard { pieces: [[GoTPiece::None; 4]; 4], pieces_num: [0; 2] };
+        for (i, c) in cells.iter().enumerate() {
+            if let Some(p) = c {
+                b.pieces[i / 4][i % 4] = GoTPiece::P(*p);
+                b.pieces_num[p.0 as usize] += 1;
+            }
+        }
+        b
     }
 }
 
@@ -195,9 +296,9 @@ impl GoTBoard {
                 }
             }
             
-            // 必须是两个单独棋子
+            // 必须是两个单独棋子，不成立时还要看另一条线
             if eat_idx[0] + eat_idx[1] != 3 {
-                return;
+                continue;
             }
 
             let eat_piece: Vec<GoTPiece> = (0..2).map(|i| self.get_near(&step.to, &dirs[i], eat_idx[i])).collect();
@@ -207,17 +308,17 @@ impl GoTBoard {
 
             // 如果两边都有棋子，不能吃掉
             if !eat_piece[0].is_none() && !eat_piece[1].is_none() {
-                return;
+                continue;
             // 都没有棋子
             } else if eat_piece[0].is_none() && eat_piece[1].is_none() {
-                return;
+                continue;
             // 一边有棋子
             } else {
                 for i in 0..2 {
                     if eat_piece[i] == GoTPiece::P(step.who.rev()) &&
-                    self.get_near(&step.to, &dirs[0], eat_idx[i]+1).is_none() {
+                    self.get_near(&step.to, &dirs[i], eat_idx[i]+1).is_none() {
                         // eat
-                        *self.get_mut_piece(&step.to.near_by(&dirs[0], eat_idx[i]).unwrap()) =
+                        *self.get_mut_piece(&step.to.near_by(&dirs[i], eat_idx[i]).unwrap()) =
                             GoTPiece::None;
                         self.pieces_num[step.who.rev().0 as usize] -= 1;
                     }
@@ -226,3 +327,125 @@ impl GoTBoard {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(diagram: &str) -> GoTBoard {
+        crate::position::parse(diagram, &GoTBoard::new()).unwrap().0
+    }
+
+    fn diagram(b: &GoTBoard) -> String {
+        crate::position::to_string(b, Player(0))
+    }
+
+    fn mv(b: &GoTBoard, from: (u8, u8), to: (u8, u8), p: u8) -> GoTBoard {
+        b.copy_put(GoTStep::new_move_step(from, to, Player(p))).expect("illegal move")
+    }
+
+    #[test]
+    fn captures() {
+        let cases = [
+            // 走成两子连，对面紧挨一个对方棋子，再往外是空位
+            ("o.../.o../x.../..xx", (1, 1), (1, 0), "o.../o.../..../..xx"),
+            // 对方棋子贴边也能吃
+            ("..../.o../o.../x.xx", (1, 1), (1, 0), "..../o.../o.../..xx"),
+            ("x.../.o../o.../..xx", (1, 1), (1, 0), "..../o.../o.../..xx"),
+            // 对方两子连不吃
+            ("o.../.o../x.../x.xx", (1, 1), (1, 0), "o.../o.../x.../x.xx"),
+            // 两头都有棋子不吃
+            ("x.../.o../o.../x..x", (1, 1), (1, 0), "x.../o.../o.../x..x"),
+            // 己方三子连不吃
+            ("o.../.o../o.../x.xx", (1, 1), (1, 0), "o.../o.../o.../x.xx"),
+            // 中间隔着空位不吃
+            ("o.../.o../..../x.xx", (1, 1), (1, 0), "o.../o.../..../x.xx"),
+            // 只看横线：竖线上没有成对
+            ("..../oox./...x/...x", (1, 0), (0, 0), "o.../.ox./...x/...x"),
+            ("..../.ox./o..x/...x", (2, 0), (1, 0), "..../oo../...x/...x"),
+            // 横竖同时吃两个
+            ("o.ox/.o../.x.x/...x", (0, 0), (0, 1), ".oo./.o../...x/...x"),
+        ];
+        for (before, from, to, after) in cases {
+            let b = mv(&board(before), from, to, 0);
+            let expect = board(after);
+            assert_eq!(diagram(&b), diagram(&expect), "{}", before);
+            assert_eq!(b.pieces_num, expect.pieces_num, "{}", before);
+        }
+    }
+
+    #[test]
+    fn captures_are_symmetric() {
+        // 同一个吃子局面在8种变换下都要吃，横线与竖线、正反方向一视同仁
+        let before = board("o.../.o../x.../..xx");
+        let step = GoTStep::new_move_step((1, 1), (1, 0), Player(0));
+        for t in Sym::ALL {
+            let b = before.transform(t).copy_put(before.transform_step(&step, t)).unwrap();
+            let (x, y) = t.pos(SIZE, (2, 0));
+            assert_eq!(b.pieces_num, [2, 2], "{:?}", t);
+            assert!(b.pieces[x as usize][y as usize] == GoTPiece::None, "{:?}", t);
+        }
+    }
+
+    #[test]
+    fn moving_between_pair_is_safe() {
+        // 自己走进对方两子旁边不会被吃
+        let b = mv(&board("o.../o.../.x../...x"), (2, 1), (2, 0), 1);
+        assert_eq!(diagram(&b), diagram(&board("o.../o.../x.../...x")));
+        assert_eq!(b.pieces_num, [2, 2]);
+    }
+
+    #[test]
+    fn legal_moves_and_over() {
+        let b = GoTBoard::new();
+        assert!(b.copy_put(GoTStep::new_move_step((0, 0), (2, 0), Player(0))).is_none());
+        assert!(b.copy_put(GoTStep::new_move_step((0, 0), (1, 1), Player(0))).is_none());
+        assert!(b.copy_put(GoTStep::new_move_step((0, 0), (1, 0), Player(1))).is_none());
+        assert!(b.copy_put(GoTStep::new_move_step((0, 0), (1, 0), Player(0))).is_some());
+        assert_eq!(b.get_possible_steps(Player(0), 0).len(), 4);
+
+        // 吃到对方只剩一子就赢
+        let b = mv(&board("o.../.o../x.../...x"), (1, 1), (1, 0), 0);
+        assert!(b.over() == GameState::Over(OutCome::Winer(Player(0))));
+        assert!(board("o.../.o../x.../..xx").over() == GameState::Running);
+    }
+
+    #[test]
+    fn score_by_pieces() {
+        let b = board("oo../o.../..x./...x");
+        assert_eq!(b.score(Player(0)), GoTBoard::PIECE);
+        assert_eq!(b.score(Player(1)), -GoTBoard::PIECE);
+        assert_eq!(b.side_scores(Player(1)), (2 * GoTBoard::PIECE, 3 * GoTBoard::PIECE));
+        // 分出胜负时按输赢计分，不再看子数
+        let b = board("oo../o.../..../...x");
+        assert_eq!(b.score(Player(0)), GoTBoard::WIN);
+        assert_eq!(b.score(Player(1)), -GoTBoard::WIN);
+    }
+
+    #[test]
+    fn possible_steps_are_legal() {
+        let b = board("o.../.ox./..x./x..o");
+        let steps = b.get_possible_steps(Player(0), 0);
+        // (0,0)两步，(1,1)三步，(3,3)两步
+        assert_eq!(steps.len(), 7);
+        assert!(steps.iter().all(|s| s.who() == Player(0) && b.copy_put(*s).is_some()));
+        assert_eq!(b.get_possible_steps(Player(1), 0).len(), 7);
+    }
+
+    #[test]
+    fn fixed_rule_regressions() {
+        // 只剩一子的一方输，以前胜方写反了
+        assert!(board("o.../..../x.../..xx").over() == GameState::Over(OutCome::Winer(Player(1))));
+        assert!(board("oo../..../x.../..o.").over() == GameState::Over(OutCome::Winer(Player(0))));
+
+        // 被吃的子在dirs[1]方向（下、右），以前总是看dirs[0]
+        let b = mv(&board(".o../o.../x.../..xx"), (0, 1), (0, 0), 0);
+        assert_eq!(diagram(&b), diagram(&board("o.../o.../..../..xx")));
+        let b = mv(&board(".ox./o.../..../x..x"), (1, 0), (0, 0), 0);
+        assert_eq!(diagram(&b), diagram(&board("oo../..../..../x..x")));
+
+        // 竖线不成立时还要看横线，以前竖线不成立就直接返回
+        let b = mv(&board("x.../.o../..ox/...x"), (1, 1), (2, 1), 0);
+        assert_eq!(diagram(&b), diagram(&board("x.../..../.oo./...x")));
+    }
+}
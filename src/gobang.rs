@@ -1,19 +1,29 @@
 mod util;
+mod cands;
+mod bit;
+mod pattern;
+mod check;
+mod vcf;
 
-use std::{collections::HashSet, fmt::Debug};
+use std::fmt::Debug;
 
-use crate::{base::{Player, Step, Board, GameState}, display::{Display, log}, ai::AI};
+use crate::{base::{Player, Step, Board, GameState, Symmetry, Sym}, display::{Display, log}, ai::AI, position::SetUp};
+
+use cands::Candidates;
+pub use bit::BitBoard;
+pub use check::find_divergence;
+pub use vcf::{solve_vcf, winning_fours, forced_reply};
 
 #[derive(Eq, Hash, PartialEq, Copy, Clone, Debug)]
 struct GoPos(u8, u8);
 
-#[derive(PartialEq, Copy, Clone, Debug)]
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
 enum GoPiece {
     P(Player),
     None,
 }
 
-#[derive(Debug, Clone)]
+#[derive(PartialEq, Debug, Clone)]
 struct GoScores {
     five: u8,
     /// 两侧没有被封，有潜力成为五的
@@ -26,15 +36,33 @@ struct GoScores {
 }
 
 impl GoScores {
-    const FIVE: i16 = 5000;
+    const FIVE: i32 = 5000;
 
     /// one, two, three, four
-    const NON_BLOCK: [i16; 4] = [0, 32, 256, 1024];
+    const NON_BLOCK: [i32; 4] = [0, 32, 256, 1024];
     /// block_two, block_three, block_four
-    const BLOCK: [i16; 3]     = [2, 32, 256];
+    const BLOCK: [i32; 3]     = [2, 32, 256];
+}
+
+/// 每个位置、每个选手一个随机数，编译期用splitmix64生成
+const fn zobrist() -> [[[u64; 2]; 15]; 15] {
+    let mut table = [[[0; 2]; 15]; 15];
+    let mut x: u64 = 0x2545f4914f6cdd1d;
+    let mut i = 0;
+    while i < 15 * 15 * 2 {
+        x = x.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = x;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        table[i / 30][i / 2 % 15][i % 2] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
 }
 
-#[derive(Copy, Clone, Debug)]
+static ZOBRIST: [[[u64; 2]; 15]; 15] = zobrist();
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
 pub struct GoStep {
     who: Player,
     pos: GoPos,
@@ -46,9 +74,18 @@ pub struct GoBoard {
     pieces_num: u8,
     /// 双方选手成绩，与Player下表对应
     scores: [GoScores; 2],
-    // 下一步可能的棋子
-    // next_pieces: HashSet<GoPos>,
-    // next_pieces: [GoPiece; 200],
+    /// Zobrist哈希，落子时增量更新
+    hash: u64,
+    /// 下一步可能的落子点，落子时增量更新
+    cands: Candidates,
+    /// 候选点到已有棋子的最大距离
+    radius: u8,
+    /// 每次落子后校验增量成绩
+    validate: bool,
+}
+
+impl GoBoard {
+    const RADIUS: u8 = 1;
 }
 
 impl Step for GoStep {
@@ -63,6 +100,14 @@ impl Step for GoStep {
     fn new_move_step(from: (u8, u8), to: (u8, u8), p: Player) -> Self {
         panic!("Go: no move");
     }
+
+    fn to_pos(&self) -> (u8, u8) {
+        (self.pos.0, self.pos.1)
+    }
+
+    fn origin_pos(&self) -> Option<(u8, u8)> {
+        None
+    }
 }
 
 impl GoStep {
@@ -73,14 +118,14 @@ impl GoStep {
 
 impl Board<GoStep> for GoBoard {
     fn new() -> Self {
-        let mut start_pieces = HashSet::new();
-        start_pieces.insert(GoPos(7, 7));
-
         GoBoard {
             pieces: [[GoPiece::None; 15]; 15],
             pieces_num: 0,
             scores: [GoScores::new(), GoScores::new()],
-            // next_pieces: start_pieces
+            hash: 0,
+            cands: Candidates::new(),
+            radius: Self::RADIUS,
+            validate: false,
         }
     }
 
@@ -89,16 +134,20 @@ impl Board<GoStep> for GoBoard {
             self.update_scores(&step);
             *self.get_mut_piece(&step.pos) = GoPiece::P(step.who);
             self.pieces_num += 1;
-            // self.update_next_pieces(&step.pos);
+            self.hash ^= ZOBRIST[step.pos.0 as usize][step.pos.1 as usize][step.who.0 as usize];
+            self.update_cands(&step.pos, true);
+            if self.validate {
+                self.validate_put(&step);
+            }
             return true;
         }
         false
     }
 
     fn over(&self) -> GameState {
-        if self.scores[0].five == 1 {
+        if self.scores[0].five > 0 {
             GameState::Over(crate::base::OutCome::Winer(Player(0)))
-        } else if self.scores[1].five == 1 {
+        } else if self.scores[1].five > 0 {
             GameState::Over(crate::base::OutCome::Winer(Player(1)))
         } else if self.pieces_num == 225 {
             // 平局判断需要加强
@@ -107,11 +156,19 @@ impl Board<GoStep> for GoBoard {
             GameState::Running
         }
     }
+
+    fn key(&self) -> u64 {
+        self.hash
+    }
+
+    fn pieces_count(&self) -> u16 {
+        self.pieces_num as u16
+    }
 }
 
 impl AI<GoStep> for GoBoard {
     /// 双方分别计分，一方减去另一方为最终得分
-    fn score(&self, player: Player) -> i16 {
+    fn score(&self, player: Player) -> i32 {
         let p0_score = self.scores[0].sum();
         let p1_score = self.scores[1].sum();
 
@@ -122,47 +179,97 @@ impl AI<GoStep> for GoBoard {
         }
     }
 
+    fn side_scores(&self, player: Player) -> (i32, i32) {
+        (self.scores[player.0 as usize].sum(), self.scores[player.rev().0 as usize].sum())
+    }
+
     fn get_possible_steps(&self, player: Player, deep: u8) -> Vec<GoStep> {
-        let mut v: Vec<(i16, GoStep)> = vec![];
+        let mut v: Vec<(i32, GoStep)> = self.cands.iter()
+            .map(|(pos, s)| (s[0] + s[1], GoStep { who: player, pos }))
+            .collect();
+        // 远离棋子的点只有天元附近可能被选中
+        for (i, j) in [(7, 7), (6, 7), (8, 7), (7, 6), (7, 8)] {
+            let step = GoStep::new(i, j, player);
+            if self.get_piece(&step.pos) == GoPiece::None && !v.iter().any(|x| x.1.pos == step.pos) {
+                v.push((-((i as i32 - 7).abs() + (j as i32 - 7).abs()), step));
+            }
+        }
+
+        // 同分按位置排，结果与候选点的存放顺序无关
+        v.sort_by(|a,b|b.0.cmp(&a.0).then((a.1.pos.0, a.1.pos.1).cmp(&(b.1.pos.0, b.1.pos.1))));
+        filter_steps(v, deep)
+    }
+}
+
+/// 按棋形分（已从高到低排好）分级过滤候选步
+fn filter_steps(v: Vec<(i32, GoStep)>, deep: u8) -> Vec<GoStep> {
+    // let b: Vec<GoStep> = v.iter().filter(|i|i.0>500).map(|i|i.1).collect();
+    // 到最后两级时，只搜索高分的
+    if deep < 5 {
+        return v.iter().filter(|x|x.0>250&&x.0<512).map(|x|x.1).collect();
+    }
+    // 最后四级
+    // if deep < 5 {
+    //     return v.iter().filter(|x|x.0>500).map(|x|x.1).collect();
+    // }
+    // 最后六级
+    // if deep < 7 {
+    //     return v.iter().filter(|x|x.0>250).map(|x|x.1).collect();
+    // }
+    // 分级过滤
+    for i in [1000, 512, 256, 0] {
+        if v[0].0 > i {
+            return v.iter().filter(|x|x.0>i).map(|x|x.1).collect();
+        }
+    }
+    v.iter().filter(|x|x.0>-2).map(|x|x.1).collect()
+}
+
+impl Symmetry<GoStep> for GoBoard {
+    fn side(&self) -> u8 {
+        15
+    }
+
+    fn cells(&self) -> Vec<Option<Player>> {
+        self.pieces.iter().flatten().map(|p| match p {
+            GoPiece::P(x) => Some(*x),
+            GoPiece::None => None,
+        }).collect()
+    }
+
+    /// 重新落子以重建评分
+    fn transform(&self, t: Sym) -> Self {
+        let mut b = GoBoard::with_radius(self.radius);
         for i in 0..15 {
             for j in 0..15 {
-                let step0 = GoStep::new(i, j, Player(0));
-                let step1 = GoStep::new(i, j, Player(1));
-                if self.get_piece(&step0.pos) == GoPiece::None {
-                    if self.has_neighbor(&step0) || self.has_neighbor(&step1) {
-                        let s0 = self.part_score(&step0);
-                        let s1 = self.part_score(&step1);
-                        v.push((s0+s1, GoStep::new(i, j, player)));
-                    } else {
-                        let s = -((i as i16 - 7).abs() + (j as i16 - 7).abs());
-                        v.push((s, GoStep::new(i, j, player)));
-                    }
+                if let GoPiece::P(p) = self.get_piece(&GoPos(i, j)) {
+                    let (x, y) = t.pos(15, (i, j));
+                    b.put(GoStep::new(x, y, p));
                 }
             }
         }
+        b
+    }
 
-        // v.sort_by_key(|i|i.0);
-        v.sort_by(|a,b|b.0.cmp(&a.0));
-        // let b: Vec<GoStep> = v.iter().filter(|i|i.0>500).map(|i|i.1).collect();
-        // 到最后两级时，只搜索高分的
-        if deep < 5 {
-            return v.iter().filter(|x|x.0>250&&x.0<512).map(|x|x.1).collect();
-        }
-        // 最后四级
-        // if deep < 5 {
-        //     return v.iter().filter(|x|x.0>500).map(|x|x.1).collect();
-        // }
-        // 最后六级
-        // if deep < 7 {
-        //     return v.iter().filter(|x|x.0>250).map(|x|x.1).collect();
-        // }
-        // 分级过滤
-        for i in [1000, 512, 256, 0] {
-            if v[0].0 > i {
-                return v.iter().filter(|x|x.0>i).map(|x|x.1).collect();
+    fn transform_step(&self, step: &GoStep, t: Sym) -> GoStep {
+        let (x, y) = t.pos(15, (step.pos.0, step.pos.1));
+        GoStep::new(x, y, step.who)
+    }
+}
+
+/// 逐个落子以重建评分与候选点
+impl SetUp<GoStep> for GoBoard {
+    fn set_up(&self, cells: &[Option<Player>]) -> Self {
+        let mut b = GoBoard::with_radius(self.radius);
+        for (i, c) in cells.iter().enumerate() {
+            if let Some(p) = c {
+                b.put(GoStep::new((i / 15) as u8, (i % 15) as u8, *p));
             }
         }
-        v.iter().filter(|x|x.0>-2).map(|x|x.1).collect()
+        if self.validate {
+            b = b.with_validation();
+        }
+        b
     }
 }
 
@@ -186,14 +293,97 @@ impl Display for GoBoard {
         (15, 15)
     }
 
+    /// 增量成绩；校验模式下后面是从头识别的棋形，从头重算的成绩与增量不一致时标出
     fn to_string(&self) -> String {
-        format!("0:{}{:?}{:?} x:{}{:?}{:?}",
+        let scores = format!("0:{}{:?}{:?} x:{}{:?}{:?}",
             self.scores[0].five,
             self.scores[0].non_block,
             self.scores[0].block,
             self.scores[1].five,
             self.scores[1].non_block,
             self.scores[1].block,
-        )
+        );
+        // 从头识别要扫整个棋盘，只在校验时做
+        if !self.validate {
+            return scores;
+        }
+        let patterns = self.recompute_patterns();
+        let drift = if self.recompute_scores() != self.scores { " 增量成绩有偏差" } else { "" };
+        format!("{} | 0:{} x:{}{}", scores, patterns[0], patterns[1], drift)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::OutCome;
+
+    fn board<B: SetUp<GoStep>>(diagram: &str) -> B {
+        crate::position::parse(diagram, &B::new()).unwrap().0
+    }
+
+    fn winner<B: Board<GoStep>>(b: &B) -> Option<Player> {
+        match b.over() {
+            GameState::Over(OutCome::Winer(p)) => Some(p),
+            _ => None,
+        }
+    }
+
+    /// 每个方向上所有能放下五子的位置，包括贴边和角上，按不同顺序落子
+    fn check_fives<B: Board<GoStep>>() {
+        let orders: [[i8; 5]; 3] = [[0, 1, 2, 3, 4], [4, 3, 2, 1, 0], [0, 4, 1, 3, 2]];
+        for (dx, dy) in [(0, 1), (1, 0), (1, -1), (1, 1)] {
+            for x in 0..15i8 {
+                for y in 0..15i8 {
+                    let (ex, ey) = (x + 4 * dx, y + 4 * dy);
+                    if !(0..15).contains(&ex) || !(0..15).contains(&ey) {
+                        continue;
+                    }
+                    for (k, order) in orders.iter().enumerate() {
+                        let p = Player(((x + y) as usize + k) as u8 % 2);
+                        let mut b = B::new();
+                        for (n, i) in order.iter().enumerate() {
+                            assert!(winner(&b).is_none(), "({},{}) dir ({},{}) after {}", x, y, dx, dy, n);
+                            b.put(GoStep::new((x + i * dx) as u8, (y + i * dy) as u8, p));
+                        }
+                        assert_eq!(winner(&b), Some(p), "({},{}) dir ({},{}) order {:?}", x, y, dx, dy, order);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn five_in_every_direction() {
+        check_fives::<GoBoard>();
+        check_fives::<BitBoard>();
+    }
+
+    #[test]
+    fn fives_by_diagram() {
+        let cases = [
+            // 两头被堵的五仍然算赢
+            ("xooooox", Some(Player(0))),
+            ("oooo.o", None),
+            ("x/.x/..x/...x/....x", Some(Player(1))),
+            ("....o/...o/..o/.o/o", Some(Player(0))),
+            ("....o/...o/..o/.o/.", None),
+            // 长连也算赢
+            ("oooooo", Some(Player(0))),
+            ("xxxx.xxxx", None),
+        ];
+        for (diagram, expect) in cases {
+            assert_eq!(winner(&board::<GoBoard>(diagram)), expect, "{}", diagram);
+            assert_eq!(winner(&board::<BitBoard>(diagram)), expect, "{}", diagram);
+        }
+    }
+
+    #[test]
+    fn rejects_occupied_and_outside() {
+        let mut b: GoBoard = board("o");
+        assert!(!b.put(GoStep::new(0, 0, Player(1))));
+        assert!(!b.put(GoStep::new(15, 0, Player(1))));
+        assert!(!b.put(GoStep::new(0, 15, Player(1))));
+        assert_eq!(b.pieces_count(), 1);
     }
 }
@@ -0,0 +1,426 @@
+use std::sync::OnceLock;
+
+use crate::{base::{Player, Step, Board, GameState, OutCome, Symmetry, Sym}, display::Display, ai::AI, position::SetUp};
+
+use super::{GoStep, GoPos, ZOBRIST, filter_steps};
+
+/// 一条线上一段（两端是边界或对方棋子）中最强的棋形
+#[derive(PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Debug)]
+pub(super) enum Shape {
+    None,
+    /// 再下一子成为眠三
+    Two,
+    /// 再下一子成为活三
+    OpenTwo,
+    /// 再下一子成为冲四
+    Three,
+    /// 再下一子成为活四
+    OpenThree,
+    /// 只有一个点能成五，包括跳四
+    Four,
+    /// 有两个以上点能成五
+    OpenFour,
+    Five,
+}
+
+impl Shape {
+    /// 与GoScores的权重对应
+    const VALUE: [i32; 8] = [0, 2, 32, 32, 256, 256, 1024, 5000];
+
+    fn value(self) -> i32 {
+        Self::VALUE[self as usize]
+    }
+}
+
+/// 线的条数：15行，15列，29条'/'斜线，29条'\'斜线
+const LINES: usize = 88;
+
+/// 棋形表，下标为(1 << 段长) | 段内己方棋子，段内其余都是空位
+fn table() -> &'static [Shape] {
+    static TABLE: OnceLock<Vec<Shape>> = OnceLock::new();
+    TABLE.get_or_init(build_table)
+}
+
+/// 长为n（5到15）的段中，只有己方棋子m时最强的棋形
+pub(super) fn segment_shape(m: u32, n: u32) -> Shape {
+    if n < 5 {
+        return Shape::None;
+    }
+    table()[(1 << n | m) as usize]
+}
+
+fn has_five(m: u32) -> bool {
+    m & m >> 1 & m >> 2 & m >> 3 & m >> 4 != 0
+}
+
+/// 长为n的段里，落下就成五的空位
+fn five_points(m: u32, n: u32) -> u32 {
+    let empty = !m & ((1 << n) - 1);
+    let mut points = 0;
+    for k in 0..5 {
+        let mut w = empty >> k;
+        for j in (0..5).filter(|j| *j != k) {
+            w &= m >> j;
+        }
+        points |= w << k;
+    }
+    points & empty
+}
+
+/// 从棋子多的往少的算：能成五的点决定四，再下一子能成的棋形决定三、二
+fn build_table() -> Vec<Shape> {
+    let mut t = vec![Shape::None; 1 << 16];
+    for n in 5..=15u32 {
+        let base = 1 << n;
+        let mut masks: Vec<u32> = (0..base).collect();
+        masks.sort_by_key(|m| std::cmp::Reverse(m.count_ones()));
+        for m in masks {
+            t[(base | m) as usize] = if has_five(m) {
+                Shape::Five
+            } else {
+                match five_points(m, n).count_ones() {
+                    0 => {
+                        let best = (0..n).filter(|p| m & 1 << p == 0)
+                            .map(|p| t[(base | m | 1 << p) as usize])
+                            .max().unwrap_or(Shape::None);
+                        match best {
+                            Shape::OpenFour => Shape::OpenThree,
+                            Shape::Four => Shape::Three,
+                            Shape::OpenThree => Shape::OpenTwo,
+                            Shape::Three => Shape::Two,
+                            _ => Shape::None,
+                        }
+                    },
+                    1 => Shape::Four,
+                    _ => Shape::OpenFour,
+                }
+            };
+        }
+    }
+    t
+}
+
+/// 一条线的评分：按对方棋子切成段，每段查表取最强棋形
+fn line_value(own: u16, opp: u16, len: u8) -> (i32, bool) {
+    let t = table();
+    let (mut value, mut five) = (0, false);
+    let mut start = 0;
+    let mut rest = opp as u32 | 1 << len;
+    while rest != 0 {
+        let end = rest.trailing_zeros();
+        let n = end - start;
+        if n >= 5 {
+            let seg = (own as u32 >> start) & ((1 << n) - 1);
+            let shape = t[(1 << n | seg) as usize];
+            value += shape.value();
+            five |= shape == Shape::Five;
+        }
+        rest &= rest - 1;
+        start = end + 1;
+    }
+    (value, five)
+}
+
+/// (x, y)所在的四条线：线号与在线上的位置
+fn lines_of(x: u8, y: u8) -> [(usize, u8); 4] {
+    let k = x + y;
+    [
+        (x as usize, y),
+        (15 + y as usize, x),
+        (30 + k as usize, x - k.saturating_sub(14)),
+        (59 + (x + 14 - y) as usize, x.min(y)),
+    ]
+}
+
+fn line_len(line: usize) -> u8 {
+    match line {
+        0..=29 => 15,
+        30..=58 => 15 - (line as i32 - 30 - 14).unsigned_abs() as u8,
+        _ => 15 - (line as i32 - 59 - 14).unsigned_abs() as u8,
+    }
+}
+
+/// 位棋盘：每条行、列、斜线存成双方各一个u16，棋形查表得到
+/// 与GoBoard接口相同，可以互换
+#[derive(Debug, Clone)]
+pub struct BitBoard {
+    /// 双方在每条线上的棋子
+    lines: [[u16; LINES]; 2],
+    /// 双方在每条线上的得分，落子时只重算经过的四条线
+    values: [[i32; LINES]; 2],
+    /// 双方总分
+    total: [i32; 2],
+    /// 双方成五的线，每条线一位
+    fives: [u128; 2],
+    pieces_num: u8,
+    hash: u64,
+}
+
+impl BitBoard {
+    fn get(&self, x: u8, y: u8) -> Option<Player> {
+        (0..2).find(|p| self.lines[*p][x as usize] & 1 << y != 0).map(|p| Player(p as u8))
+    }
+
+    fn rows(&self) -> [u16; 15] {
+        let mut rows = [0; 15];
+        for (x, r) in rows.iter_mut().enumerate() {
+            *r = self.lines[0][x] | self.lines[1][x];
+        }
+        rows
+    }
+
+    /// 在(x, y)落子后player在四条线上增加的分数
+    fn gain(&self, x: u8, y: u8, player: usize) -> i32 {
+        lines_of(x, y).iter().map(|(l, i)| {
+            let own = self.lines[player][*l] | 1 << i;
+            line_value(own, self.lines[1 - player][*l], line_len(*l)).0 - self.values[player][*l]
+        }).sum()
+    }
+}
+
+impl Board<GoStep> for BitBoard {
+    fn new() -> Self {
+        BitBoard {
+            lines: [[0; LINES]; 2],
+            values: [[0; LINES]; 2],
+            total: [0; 2],
+            fives: [0; 2],
+            pieces_num: 0,
+            hash: 0,
+        }
+    }
+
+    fn put(&mut self, step: GoStep) -> bool {
+        let GoPos(x, y) = step.pos;
+        if !step.pos.valid() || self.get(x, y).is_some() {
+            return false;
+        }
+        let me = step.who.0 as usize;
+        for (l, i) in lines_of(x, y) {
+            self.lines[me][l] |= 1 << i;
+            for p in 0..2 {
+                let (value, five) = line_value(self.lines[p][l], self.lines[1 - p][l], line_len(l));
+                self.total[p] += value - self.values[p][l];
+                self.values[p][l] = value;
+                if five {
+                    self.fives[p] |= 1 << l;
+                } else {
+                    self.fives[p] &= !(1 << l);
+                }
+            }
+        }
+        self.pieces_num += 1;
+        self.hash ^= ZOBRIST[x as usize][y as usize][me];
+        true
+    }
+
+    fn over(&self) -> GameState {
+        if self.fives[0] != 0 {
+            GameState::Over(OutCome::Winer(Player(0)))
+        } else if self.fives[1] != 0 {
+            GameState::Over(OutCome::Winer(Player(1)))
+        } else if self.pieces_num == 225 {
+            GameState::Over(OutCome::Draw)
+        } else {
+            GameState::Running
+        }
+    }
+
+    fn key(&self) -> u64 {
+        self.hash
+    }
+
+    fn pieces_count(&self) -> u16 {
+        self.pieces_num as u16
+    }
+}
+
+impl AI<GoStep> for BitBoard {
+    fn score(&self, player: Player) -> i32 {
+        let (me, ot) = self.side_scores(player);
+        me - ot
+    }
+
+    fn side_scores(&self, player: Player) -> (i32, i32) {
+        (self.total[player.0 as usize], self.total[player.rev().0 as usize])
+    }
+
+    /// 与已有棋子相邻的空位，按双方落子后增加的分数排序
+    fn get_possible_steps(&self, player: Player, deep: u8) -> Vec<GoStep> {
+        let rows = self.rows();
+        let mut v = vec![];
+        for x in 0..15 {
+            let mut near = rows[x];
+            if x > 0 {
+                near |= rows[x - 1];
+            }
+            if x < 14 {
+                near |= rows[x + 1];
+            }
+            let mut cand = (near | near << 1 | near >> 1) & !rows[x] & 0x7fff;
+            while cand != 0 {
+                let y = cand.trailing_zeros() as u8;
+                cand &= cand - 1;
+                let s = self.gain(x as u8, y, 0) + self.gain(x as u8, y, 1);
+                v.push((s, GoStep::new(x as u8, y, player)));
+            }
+        }
+        // 远离棋子的点只有天元附近可能被选中
+        for (i, j) in [(7, 7), (6, 7), (8, 7), (7, 6), (7, 8)] {
+            if self.get(i, j).is_none() && !v.iter().any(|s: &(i32, GoStep)| s.1.pos == GoPos(i, j)) {
+                v.push((-((i as i32 - 7).abs() + (j as i32 - 7).abs()), GoStep::new(i, j, player)));
+            }
+        }
+        v.sort_by(|a,b|b.0.cmp(&a.0).then((a.1.pos.0, a.1.pos.1).cmp(&(b.1.pos.0, b.1.pos.1))));
+        filter_steps(v, deep)
+    }
+}
+
+impl Symmetry<GoStep> for BitBoard {
+    fn side(&self) -> u8 {
+        15
+    }
+
+    fn cells(&self) -> Vec<Option<Player>> {
+        (0..15).flat_map(|x| (0..15).map(move |y| (x, y))).map(|(x, y)| self.get(x, y)).collect()
+    }
+
+    fn transform(&self, t: Sym) -> Self {
+        let mut b = BitBoard::new();
+        for x in 0..15 {
+            for y in 0..15 {
+                if let Some(p) = self.get(x, y) {
+                    b.put(GoStep::new_put_step(t.pos(15, (x, y)), p));
+                }
+            }
+        }
+        b
+    }
+
+    fn transform_step(&self, step: &GoStep, t: Sym) -> GoStep {
+        GoStep::new_put_step(t.pos(15, step.to_pos()), step.who)
+    }
+}
+
+impl SetUp<GoStep> for BitBoard {
+    fn set_up(&self, cells: &[Option<Player>]) -> Self {
+        let mut b = BitBoard::new();
+        for (i, c) in cells.iter().enumerate() {
+            if let Some(p) = c {
+                b.put(GoStep::new_put_step(((i / 15) as u8, (i % 15) as u8), *p));
+            }
+        }
+        b
+    }
+}
+
+impl Display for BitBoard {
+    fn to_array(&self) -> Vec<Vec<char>> {
+        (0..15).map(|x| (0..15).map(|y| match self.get(x, y) {
+            Some(Player(0)) => '0',
+            Some(_) => 'x',
+            None => ' ',
+        }).collect()).collect()
+    }
+
+    fn size(&self) -> (u8, u8) {
+        (15, 15)
+    }
+
+    fn to_string(&self) -> String {
+        format!("0:{} x:{}", self.total[0], self.total[1])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    /// 'x'为己方，'o'为对方，'.'为空
+    fn shape(line: &str) -> Shape {
+        let own = line.chars().enumerate().filter(|c| c.1 == 'x').fold(0, |m, c| m | 1 << c.0);
+        let opp = line.chars().enumerate().filter(|c| c.1 == 'o').fold(0, |m, c| m | 1 << c.0);
+        let t = table();
+        let mut best = Shape::None;
+        let mut start = 0;
+        let mut rest = opp | 1 << line.len();
+        while rest != 0 {
+            let end = (rest as u32).trailing_zeros();
+            let n = end - start;
+            if n >= 5 {
+                best = best.max(t[(1 << n | (own >> start) & ((1 << n) - 1)) as usize]);
+            }
+            rest &= rest - 1;
+            start = end + 1;
+        }
+        best
+    }
+
+    #[test]
+    fn classifies_shapes() {
+        for (line, expect) in [
+            ("..xxxxx..", Shape::Five),
+            (".xxxx.", Shape::OpenFour),
+            ("oxxxx.", Shape::Four),
+            ("x.xxx", Shape::Four),
+            ("xx.xx", Shape::Four),
+            ("..xxx..", Shape::OpenThree),
+            (".xx.x.", Shape::OpenThree),
+            ("oxxx..", Shape::Three),
+            (".xxx.o", Shape::Three),
+            ("x..xx", Shape::Three),
+            ("..xx..", Shape::OpenTwo),
+            (".x.x..", Shape::OpenTwo),
+            ("oxx...", Shape::Two),
+            ("oxxxxo", Shape::None),
+            ("xxxx", Shape::None),
+        ] {
+            assert_eq!(shape(line), expect, "{}", line);
+        }
+    }
+
+    fn brute_five(b: &BitBoard) -> Option<Player> {
+        for x in 0..15i8 {
+            for y in 0..15i8 {
+                let p = match b.get(x as u8, y as u8) {
+                    Some(p) => p,
+                    None => continue,
+                };
+                for (dx, dy) in [(0, 1), (1, 0), (1, 1), (1, -1)] {
+                    if (1..5).all(|i| {
+                        let (a, c) = (x + dx * i, y + dy * i);
+                        (0..15).contains(&a) && (0..15).contains(&c) && b.get(a as u8, c as u8) == Some(p)
+                    }) {
+                        return Some(p);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn incremental_matches_recompute() {
+        let mut rng = StdRng::seed_from_u64(11);
+        for _ in 0..50 {
+            let mut b = BitBoard::new();
+            let mut player = Player(0);
+            while b.over() == GameState::Running {
+                let pos = (rng.gen_range(0..15), rng.gen_range(0..15));
+                if b.put(GoStep::new_put_step(pos, player)) {
+                    player = player.rev();
+                    let fresh = b.transform(Sym::ALL[0]);
+                    assert_eq!(fresh.total, b.total);
+                    let expect = match brute_five(&b) {
+                        Some(p) => GameState::Over(OutCome::Winer(p)),
+                        None if b.pieces_num == 225 => GameState::Over(OutCome::Draw),
+                        None => GameState::Running,
+                    };
+                    assert!(b.over() == expect);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use crate::base::{Player, Board};
+
+use super::{GoBoard, GoStep, GoPos, GoPiece, GoScores};
+
+/// 棋形最远看到5格外
+const REACH: i8 = 5;
+
+const DIRS: [(i8, i8); 8] = [(0, 1), (0, -1), (1, 0), (-1, 0), (1, -1), (-1, 1), (1, 1), (-1, -1)];
+
+/// 候选点：距已有棋子radius以内的空位，并缓存双方在该点落子的棋形分
+#[derive(Debug, Clone)]
+pub(super) struct Candidates {
+    /// 每个格子radius以内（不含自身）的棋子数
+    near: [[u8; 15]; 15],
+    /// 候选点，下标为x*15+y，与idx互为索引
+    list: [u8; 225],
+    len: u8,
+    /// 格子在list中的位置，不在时为NONE
+    idx: [u8; 225],
+    /// 双方在候选点落子的part_score
+    score: [[[i32; 2]; 15]; 15],
+}
+
+/// 可撤销的一步，记下落子前的成绩
+#[derive(Debug, Clone)]
+pub struct GoUndo {
+    step: GoStep,
+    scores: [GoScores; 2],
+}
+
+impl Candidates {
+    const NONE: u8 = u8::MAX;
+
+    pub(super) fn new() -> Self {
+        Candidates {
+            near: [[0; 15]; 15],
+            list: [0; 225],
+            len: 0,
+            idx: [Self::NONE; 225],
+            score: [[[0; 2]; 15]; 15],
+        }
+    }
+
+    fn contains(&self, pos: &GoPos) -> bool {
+        self.idx[pos.0 as usize * 15 + pos.1 as usize] != Self::NONE
+    }
+
+    fn insert(&mut self, pos: &GoPos) {
+        let i = pos.0 * 15 + pos.1;
+        if self.idx[i as usize] == Self::NONE {
+            self.idx[i as usize] = self.len;
+            self.list[self.len as usize] = i;
+            self.len += 1;
+        }
+    }
+
+    fn remove(&mut self, pos: &GoPos) {
+        let i = (pos.0 * 15 + pos.1) as usize;
+        let at = self.idx[i];
+        if at != Self::NONE {
+            self.len -= 1;
+            let last = self.list[self.len as usize];
+            self.list[at as usize] = last;
+            self.idx[last as usize] = at;
+            self.idx[i] = Self::NONE;
+        }
+    }
+
+    /// 候选点及双方在该点的棋形分
+    pub(super) fn iter(&self) -> impl Iterator<Item = (GoPos, [i32; 2])> + '_ {
+        self.list[..self.len as usize].iter().map(|i| {
+            let pos = GoPos(i / 15, i % 15);
+            (pos, self.score[pos.0 as usize][pos.1 as usize])
+        })
+    }
+}
+
+pub(super) fn offset(pos: &GoPos, dx: i8, dy: i8) -> Option<GoPos> {
+    let (x, y) = (pos.0 as i8 + dx, pos.1 as i8 + dy);
+    if (0..15).contains(&x) && (0..15).contains(&y) {
+        Some(GoPos(x as u8, y as u8))
+    } else {
+        None
+    }
+}
+
+impl GoBoard {
+    /// 空棋盘，候选点取已有棋子radius以内的空位
+    pub fn with_radius(radius: u8) -> Self {
+        let mut b = GoBoard::new();
+        b.radius = radius.max(1);
+        b
+    }
+
+    /// 落子，返回用于撤销的记录
+    pub fn play(&mut self, step: GoStep) -> Option<GoUndo> {
+        let scores = self.scores.clone();
+        if self.put(step) {
+            Some(GoUndo { step, scores })
+        } else {
+            None
+        }
+    }
+
+    /// 撤销play走的一步，必须按相反顺序撤销。只有check.rs用到，搜索复制棋盘更快，见bench
+    pub fn undo(&mut self, undo: GoUndo) {
+        let pos = undo.step.pos;
+        *self.get_mut_piece(&pos) = GoPiece::None;
+        self.pieces_num -= 1;
+        self.hash ^= super::ZOBRIST[pos.0 as usize][pos.1 as usize][undo.step.who.0 as usize];
+        self.scores = undo.scores;
+        self.update_cands(&pos, false);
+    }
+
+    /// 棋子放上（placed）或拿走后，更新候选点与缓存的棋形分
+    pub(super) fn update_cands(&mut self, pos: &GoPos, placed: bool) {
+        let r = self.radius as i8;
+        if placed {
+            self.cands.remove(pos);
+        } else if self.cands.near[pos.0 as usize][pos.1 as usize] > 0 {
+            self.cands.insert(pos);
+            self.refresh_score(pos);
+        }
+
+        for dx in -r..=r {
+            for dy in -r..=r {
+                let q = match offset(pos, dx, dy) {
+                    Some(q) if q != *pos => q,
+                    _ => continue,
+                };
+                let near = &mut self.cands.near[q.0 as usize][q.1 as usize];
+                if placed {
+                    *near += 1;
+                } else {
+                    *near -= 1;
+                }
+                let near = *near;
+                if self.get_piece(&q) != GoPiece::None {
+                    continue;
+                }
+                if placed && near == 1 {
+                    self.cands.insert(&q);
+                    self.refresh_score(&q);
+                } else if !placed && near == 0 {
+                    self.cands.remove(&q);
+                }
+            }
+        }
+
+        // 同一条线上5格以内的候选点棋形会变
+        for (dx, dy) in DIRS {
+            for i in 1..=REACH {
+                if let Some(q) = offset(pos, dx * i, dy * i) {
+                    if self.cands.contains(&q) {
+                        self.refresh_score(&q);
+                    }
+                }
+            }
+        }
+    }
+
+    fn refresh_score(&mut self, pos: &GoPos) {
+        let s = [0, 1].map(|p| self.part_score(&GoStep { who: Player(p), pos: *pos }));
+        self.cands.score[pos.0 as usize][pos.1 as usize] = s;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::base::{GameState, Step};
+
+    /// 逐格重新计算的候选点及棋形分
+    fn brute_force(board: &GoBoard) -> Vec<(GoPos, [i32; 2])> {
+        let r = board.radius as i8;
+        let mut v = vec![];
+        for i in 0..15 {
+            for j in 0..15 {
+                let pos = GoPos(i, j);
+                if board.get_piece(&pos) != GoPiece::None {
+                    continue;
+                }
+                let near = (-r..=r).flat_map(|dx| (-r..=r).map(move |dy| (dx, dy)))
+                    .filter_map(|(dx, dy)| offset(&pos, dx, dy).filter(|q| *q != pos))
+                    .any(|q| board.get_piece(&q) != GoPiece::None);
+                if near {
+                    let s = [0, 1].map(|p| board.part_score(&GoStep { who: Player(p), pos }));
+                    v.push((pos, s));
+                }
+            }
+        }
+        v
+    }
+
+    fn sorted(board: &GoBoard) -> Vec<(GoPos, [i32; 2])> {
+        let mut v: Vec<_> = board.cands.iter().collect();
+        v.sort_by_key(|x| (x.0 .0, x.0 .1));
+        v
+    }
+
+    #[test]
+    fn incremental_matches_brute_force_and_undo() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for radius in [1, 2, 3] {
+            for _ in 0..10 {
+                let mut board = GoBoard::with_radius(radius);
+                let mut undos = vec![];
+                let mut snapshots = vec![];
+                let mut player = Player(0);
+                while board.over() == GameState::Running && undos.len() < 60 {
+                    let pos = (rng.gen_range(0..15), rng.gen_range(0..15));
+                    let before = (sorted(&board), board.hash, board.scores.clone());
+                    if let Some(u) = board.play(GoStep::new_put_step(pos, player)) {
+                        undos.push(u);
+                        snapshots.push(before);
+                        assert_eq!(sorted(&board), brute_force(&board));
+                        player = player.rev();
+                    }
+                }
+                while let Some(u) = undos.pop() {
+                    board.undo(u);
+                    let (cands, hash, scores) = snapshots.pop().unwrap();
+                    assert_eq!(sorted(&board), cands);
+                    assert_eq!((board.hash, &board.scores), (hash, &scores));
+                }
+                assert_eq!(board.pieces_num, 0);
+            }
+        }
+    }
+}
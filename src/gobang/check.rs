@@ -0,0 +1,170 @@
+use std::fmt;
+
+use rand::Rng;
+
+use crate::{base::{Player, Step, Board, GameState}, display::{Display, log}, record};
+
+use super::{GoBoard, GoStep, GoScores, pattern::lines};
+
+/// 增量成绩与从头识别的结果第一次不一致的局面
+pub struct Divergence {
+    /// 从空棋盘走到该局面的步子，最后一步之后开始不一致
+    pub steps: Vec<GoStep>,
+    /// 不一致出现在悔掉这一步之后
+    pub undone: Option<GoStep>,
+    pub board: GoBoard,
+    incremental: [GoScores; 2],
+    recomputed: [GoScores; 2],
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "第{}步后不一致：{}", self.steps.len(), record::game_to_string(&self.steps))?;
+        if let Some(s) = &self.undone {
+            writeln!(f, "悔掉{}之后", record::step_to_string(s))?;
+        }
+        for row in self.board.to_array() {
+            let row: String = row.iter().map(|c| if *c == ' ' { '.' } else { *c }).collect();
+            writeln!(f, "{}", row)?;
+        }
+        for p in 0..2 {
+            writeln!(f, "{}: 增量 {:?}", p, self.incremental[p])?;
+            writeln!(f, "{}: 重算 {:?}", p, self.recomputed[p])?;
+        }
+        let [p0, p1] = self.board.recompute_patterns();
+        write!(f, "棋形 0:{} x:{}", p0, p1)
+    }
+}
+
+impl GoBoard {
+    /// 校验模式：每次落子后从头重算成绩并与增量结果比较，不一致时写日志
+    pub fn with_validation(mut self) -> Self {
+        self.validate = true;
+        self
+    }
+
+    /// 按增量成绩的计数规则逐条线从头重算，跳三、跳四等细分棋形见recompute_patterns
+    pub(super) fn recompute_scores(&self) -> [GoScores; 2] {
+        let mut res = [GoScores::new(), GoScores::new()];
+        for (_, line) in lines() {
+            let cells = self.line_cells(&line);
+            for (i, r) in res.iter_mut().enumerate() {
+                r.add(&GoScores::of_line(&cells, Player(i as u8)));
+            }
+        }
+        res
+    }
+
+    /// 增量成绩与从头重算的不一致时返回两者
+    pub(super) fn check_scores(&self) -> Option<([GoScores; 2], [GoScores; 2])> {
+        let recomputed = self.recompute_scores();
+        if recomputed == self.scores {
+            None
+        } else {
+            Some((self.scores.clone(), recomputed))
+        }
+    }
+
+    pub(super) fn validate_put(&self, step: &GoStep) {
+        if let Some((inc, re)) = self.check_scores() {
+            log(format!("成绩不一致 {}: 增量{:?} 重算{:?}", record::step_to_string(step), inc, re));
+        }
+    }
+}
+
+/// 悔棋的概率，悔掉后换一步走，校验撤销
+const UNDO_RATE: f64 = 0.1;
+
+/// 双方随机落子下games局，每步后校验，偶尔悔一步再校验，返回第一个不一致的局面
+pub fn find_divergence<R: Rng>(games: usize, rng: &mut R) -> Option<Divergence> {
+    find_divergence_from(&GoBoard::new(), games, rng)
+}
+
+/// 每局都从start开始
+fn find_divergence_from<R: Rng>(start: &GoBoard, games: usize, rng: &mut R) -> Option<Divergence> {
+    for _ in 0..games {
+        let mut board = start.clone();
+        let mut steps = vec![];
+        let mut player = Player(0);
+        while board.over() == GameState::Running {
+            let step = GoStep::new_put_step((rng.gen_range(0..15), rng.gen_range(0..15)), player);
+            let Some(undo) = board.play(step) else {
+                continue;
+            };
+            steps.push(step);
+            let mut undone = None;
+            if rng.gen_bool(UNDO_RATE) {
+                board.undo(undo);
+                undone = steps.pop();
+            } else {
+                player = player.rev();
+            }
+            if let Some((incremental, recomputed)) = board.check_scores() {
+                return Some(Divergence { steps, undone, board, incremental, recomputed });
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    fn play(line: &str) -> GoBoard {
+        let mut board = GoBoard::new();
+        for s in record::parse_game(line, &board.clone()).unwrap() {
+            board.put(s);
+        }
+        board
+    }
+
+    #[test]
+    fn edge_lines_match_recompute() {
+        // 贴边、在角上的连子，以前dir_add在边上不移动会读错格子
+        for line in [
+            "0,0 7,7 0,1 10,3 0,2",
+            "14,14 7,7 14,13 10,3 14,12",
+            "3,0 7,7 4,0 10,3 5,0 3,10 6,0",
+            "0,14 7,7 1,13 10,3 2,12",
+            "14,0 0,7 13,1 3,3 12,2 7,11 11,3",
+            // 靠近角的'/'活二，以前只数连子一侧的空位，没有计入
+            "12,10 7,7 13,9",
+        ] {
+            let board = play(line);
+            assert!(board.check_scores().is_none(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn random_games_match_recompute() {
+        let mut rng = StdRng::seed_from_u64(0);
+        if let Some(d) = find_divergence(50, &mut rng) {
+            panic!("{}", d);
+        }
+    }
+
+    #[test]
+    fn reports_first_divergence() {
+        // 故意弄错一个增量成绩，第一步之后就该报告
+        let mut start = GoBoard::new();
+        start.scores[0].non_block[1] += 1;
+        let mut rng = StdRng::seed_from_u64(11);
+        let d = find_divergence_from(&start, 10, &mut rng).expect("弄错的成绩没有被发现");
+        assert!(d.steps.len() + d.undone.iter().count() == 1);
+        assert_eq!(d.incremental[0].non_block[1], d.recomputed[0].non_block[1] + 1);
+        assert_eq!(d.incremental[1], d.recomputed[1]);
+        assert!(d.to_string().contains("不一致"));
+    }
+
+    #[test]
+    fn display_recomputes_only_when_validating() {
+        let mut board = play("7,7 8,8 7,8");
+        board.scores[1].non_block[0] += 1;
+        assert!(!board.to_string().contains('|'));
+        let board = board.with_validation();
+        assert!(board.to_string().contains("增量成绩有偏差"));
+    }
+}
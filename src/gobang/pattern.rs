@@ -0,0 +1,231 @@
+use std::fmt;
+
+use crate::base::Player;
+
+use super::{GoBoard, GoPos, GoPiece, GoScores, bit::{self, Shape}};
+
+/// 棋形，跳四、跳三指中间隔着空位的四和三
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Pattern {
+    Five,
+    /// 有两个以上点能成五
+    OpenFour,
+    /// 只有一个点能成五的连四
+    Four,
+    /// 只有一个点能成五，点在中间，如X_XXX、XX_XX
+    SplitFour,
+    /// 再下一子成为活四的连三
+    OpenThree,
+    /// 再下一子成为活四，中间有空，如XX_X
+    SplitThree,
+    /// 再下一子只能成为冲四或跳四
+    Three,
+    /// 再下一子成为活三或跳三
+    OpenTwo,
+    /// 再下一子成为眠三
+    Two,
+}
+
+impl Pattern {
+    pub const ALL: [Pattern; 9] = [
+        Pattern::Five, Pattern::OpenFour, Pattern::Four, Pattern::SplitFour,
+        Pattern::OpenThree, Pattern::SplitThree, Pattern::Three, Pattern::OpenTwo, Pattern::Two,
+    ];
+    const NAMES: [&'static str; 9] = ["成五", "活四", "冲四", "跳四", "活三", "跳三", "眠三", "活二", "眠二"];
+    /// 与GoScores的权重对应
+    const VALUE: [i32; 9] = [GoScores::FIVE, 1024, 256, 256, 256, 256, 32, 32, 2];
+
+    /// 按连续与否细分表中的棋形
+    fn from_shape(shape: Shape, split: bool) -> Option<Pattern> {
+        Some(match shape {
+            Shape::None => return None,
+            Shape::Two => Pattern::Two,
+            Shape::OpenTwo => Pattern::OpenTwo,
+            Shape::Three => Pattern::Three,
+            Shape::OpenThree if split => Pattern::SplitThree,
+            Shape::OpenThree => Pattern::OpenThree,
+            Shape::Four if split => Pattern::SplitFour,
+            Shape::Four => Pattern::Four,
+            Shape::OpenFour => Pattern::OpenFour,
+            Shape::Five => Pattern::Five,
+        })
+    }
+}
+
+/// 一方在横、竖、'/'、'\'四个方向上各棋形的个数
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct Patterns {
+    pub dirs: [[u8; 9]; 4],
+}
+
+impl Patterns {
+    pub fn count(&self, p: Pattern) -> u32 {
+        self.dirs.iter().map(|d| d[p as usize] as u32).sum()
+    }
+
+    pub fn value(&self) -> i32 {
+        Pattern::ALL.iter().map(|p| self.count(*p) as i32 * Pattern::VALUE[*p as usize]).sum()
+    }
+}
+
+impl fmt::Display for Patterns {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for p in Pattern::ALL {
+            match self.count(p) {
+                0 => (),
+                n => write!(f, "{}{} ", Pattern::NAMES[p as usize], n)?,
+            }
+        }
+        write!(f, "={}", self.value())
+    }
+}
+
+/// 一条线上who的棋形，线的两端和对方棋子都是墙
+pub fn line_patterns(line: &[Option<Player>], who: Player) -> Vec<Pattern> {
+    let mut v = vec![];
+    let mut start = 0;
+    while start < line.len() {
+        let end = (start..line.len()).find(|i| line[*i].is_some_and(|p| p != who)).unwrap_or(line.len());
+        if end - start >= 5 {
+            let seg: Vec<bool> = line[start..end].iter().map(|p| p.is_some()).collect();
+            segment_patterns(&seg, &mut v);
+        }
+        start = end + 1;
+    }
+    v
+}
+
+/// 段内只有己方棋子和空位。棋子按最多隔一个空位分组，
+/// 每组只看自己和两侧4格以内的空位，查表定形，有空隔的再区分为跳四、跳三
+fn segment_patterns(seg: &[bool], v: &mut Vec<Pattern>) {
+    let mut groups: Vec<Vec<usize>> = vec![];
+    for s in (0..seg.len()).filter(|i| seg[*i]) {
+        match groups.last_mut() {
+            Some(g) if s - g[g.len() - 1] <= 2 => g.push(s),
+            _ => groups.push(vec![s]),
+        }
+    }
+    for g in groups {
+        let (first, last) = (g[0], g[g.len() - 1]);
+        let lo = first.saturating_sub(4);
+        let hi = (last + 5).min(seg.len());
+        let mask = g.iter().fold(0, |m, s| m | 1 << (s - lo));
+        let split = last - first + 1 != g.len();
+        if let Some(p) = Pattern::from_shape(bit::segment_shape(mask, (hi - lo) as u32), split) {
+            v.push(p);
+        }
+    }
+}
+
+/// 四个方向上所有的线，每条线从起点出发直到出界
+pub(super) fn lines() -> impl Iterator<Item = (usize, Vec<GoPos>)> {
+    let dirs: [(i8, i8); 4] = [(0, 1), (1, 0), (1, -1), (1, 1)];
+    dirs.into_iter().enumerate().flat_map(|(d, (dx, dy))| {
+        let starts: Vec<(i8, i8)> = match d {
+            0 => (0..15).map(|x| (x, 0)).collect(),
+            1 => (0..15).map(|y| (0, y)).collect(),
+            2 => (0..15).map(|y| (0, y)).chain((1..15).map(|x| (x, 14))).collect(),
+            _ => (0..15).map(|y| (0, y)).chain((1..15).map(|x| (x, 0))).collect(),
+        };
+        starts.into_iter().map(move |(mut x, mut y)| {
+            let mut line = vec![];
+            while (0..15).contains(&x) && (0..15).contains(&y) {
+                line.push(GoPos(x as u8, y as u8));
+                x += dx;
+                y += dy;
+            }
+            (d, line)
+        })
+    })
+}
+
+impl GoBoard {
+    /// 逐条线从头识别双方棋形，不依赖增量维护的scores
+    pub fn recompute_patterns(&self) -> [Patterns; 2] {
+        let mut res = [Patterns::default(), Patterns::default()];
+        for (d, line) in lines() {
+            let cells = self.line_cells(&line);
+            for (i, r) in res.iter_mut().enumerate() {
+                for p in line_patterns(&cells, Player(i as u8)) {
+                    r.dirs[d][p as usize] += 1;
+                }
+            }
+        }
+        res
+    }
+
+    pub(super) fn line_cells(&self, line: &[GoPos]) -> Vec<Option<Player>> {
+        line.iter().map(|pos| match self.get_piece(pos) {
+            GoPiece::P(p) => Some(p),
+            GoPiece::None => None,
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::{Board, Step};
+    use crate::gobang::GoStep;
+    use Pattern::*;
+
+    /// x为己方，o为对方，.为空
+    fn parse(s: &str) -> Vec<Pattern> {
+        let line: Vec<Option<Player>> = s.chars().map(|c| match c {
+            'x' => Some(Player(0)),
+            'o' => Some(Player(1)),
+            _ => None,
+        }).collect();
+        line_patterns(&line, Player(0))
+    }
+
+    #[test]
+    fn classifies_line_patterns() {
+        let cases: [(&str, &[Pattern]); 15] = [
+            ("..xxxxx..", &[Five]),
+            ("..xxxx..", &[OpenFour]),
+            ("oxxxx...", &[Four]),
+            ("...x.xxx...", &[SplitFour]),
+            ("..xx.xx..", &[SplitFour]),
+            ("o.xxx.x.o", &[SplitFour]),
+            ("...xxx...", &[OpenThree]),
+            ("..xx.x...", &[SplitThree]),
+            ("oxxx....", &[Three]),
+            ("ox.xx...", &[Three]),
+            ("..x.x.x..", &[Three]),
+            ("...xx...", &[OpenTwo]),
+            ("oxx.....", &[Two]),
+            ("oxxx.o", &[]),
+            ("..xxx...ox.xx.o", &[OpenThree, Three]),
+        ];
+        for (line, expect) in cases {
+            assert_eq!(parse(line), expect, "{}", line);
+        }
+    }
+
+    #[test]
+    fn recompute_by_direction() {
+        let mut board = GoBoard::new();
+        // 横向跳三，竖向和'/'各一个活二，另一方在角上的'\'眠三
+        for (x, y, p) in [(7, 5, 0), (7, 6, 0), (7, 8, 0), (8, 5, 0), (0, 0, 1), (1, 1, 1), (2, 2, 1)] {
+            board.put(GoStep::new_put_step((x, y), Player(p)));
+        }
+        let [p0, p1] = board.recompute_patterns();
+        assert_eq!(p0.dirs[0][SplitThree as usize], 1);
+        assert_eq!(p0.dirs[1][OpenTwo as usize], 1);
+        assert_eq!(p0.dirs[2][OpenTwo as usize], 1);
+        assert_eq!(p0.count(SplitThree) + p0.count(OpenTwo), 3);
+        assert_eq!(p1.dirs[3][Three as usize], 1);
+        assert_eq!(p1.count(Three), 1);
+    }
+
+    #[test]
+    fn contiguous_shapes_match_incremental() {
+        let mut board = GoBoard::new();
+        for (x, y, p) in [(7, 6, 0), (0, 0, 1), (7, 7, 0), (0, 14, 1), (7, 8, 0)] {
+            board.put(GoStep::new_put_step((x, y), Player(p)));
+        }
+        assert_eq!(board.recompute_patterns()[0].count(OpenThree), 1);
+        assert_eq!(board.recompute_scores(), board.scores);
+    }
+}
@@ -1,7 +1,6 @@
 use crate::{base::Player, display::log};
 
-use super::{GoBoard, GoStep, GoScores, GoPos, GoPiece};
-use Dir::*;
+use super::{GoBoard, GoStep, GoScores, GoPos, GoPiece, cands::offset};
 
 /// Direction, bool表示正负方向（偏右下角为正，偏左上角为负）
 #[derive(PartialEq)]
@@ -95,103 +94,44 @@ impl GoBoard {
         &mut self.pieces[pos.0 as usize][pos.1 as usize]
     }
 
+    /// 在落子之前调用。落子只影响过该点的四条线，每条线减去落子前、加上落子后的成绩，
+    /// 与check.rs中逐条线的重算用同一个规则
     pub(super) fn update_scores(&mut self, step: &GoStep) {
-        let score = &mut self.scores;
-        let me_idx = step.who.0 as usize;
-        let ot_idx = step.who.rev().0 as usize;
-
-        // 四个维度
-        for i in 0..4 {
-            let fcond = get_cond_by_dir(&self.pieces, step, &Dir::DIRS[i*2]);
-            let bcond = get_cond_by_dir(&self.pieces, step, &Dir::DIRS[i*2+1]);
-
-            // 两边都有棋子
-            if fcond.piece != GoPiece::None && bcond.piece != GoPiece::None {
-                // 两边都是自己的棋
-                if GoPiece::P(step.who) == fcond.piece && GoPiece::P(step.who) == bcond.piece {
-                    score[me_idx].remove(fcond.num, fcond.block);
-                    score[me_idx].remove(bcond.num, bcond.block);
-                    score[me_idx].insert(fcond.num + 1 + bcond.num, fcond.block || bcond.block);
-                // 一边是自己的棋
-                } else if GoPiece::P(step.who) == fcond.piece || GoPiece::P(step.who) == bcond.piece {
-                    let c = if GoPiece::P(step.who) == fcond.piece {&fcond} else {&bcond};
-
-                    score[me_idx].remove(c.num, c.block);
-                    if !c.block {
-                        score[me_idx].insert(c.num + 1, true);
-                    }
-                // 都是对方棋子
-                } else {
-                    if fcond.block {
-                        score[ot_idx].remove(fcond.num, fcond.block);
-                    }
-                    if bcond.block {
-                        score[ot_idx].remove(bcond.num, bcond.block);
-                    }
-                }
-
-            // 都没有棋子
-            } else if fcond.piece == GoPiece::None && bcond.piece == GoPiece::None {
-                score[me_idx].insert(1, false);
-            
-            // 只有一边有棋子
-            } else {
-                let (c, n) = if fcond.piece == GoPiece::None {
-                    (&bcond, &fcond)
-                } else {
-                    (&fcond, &bcond)
-                };
-
-                if c.piece == GoPiece::P(step.who) {
-                    score[me_idx].remove(c.num, c.block);
-                    if c.num + n.num + 1 >= 5 {
-                        score[me_idx].insert(c.num + 1, c.block);
-                    }
-                } else if c.piece == GoPiece::P(step.who.rev()) {
-                    score[ot_idx].remove(c.num, c.block);
-                    if !c.block {
-                        score[ot_idx].insert(c.num, true);
-                    }
-                }
+        for dir in [Dir::Horiz(true), Dir::Verti(true), Dir::FSlash(true), Dir::BSlash(true)] {
+            let (mut cells, len, at) = self.line_through(&step.pos, &dir);
+            let line = &mut cells[..len];
+            let before = [0, 1].map(|p| GoScores::of_line(line, Player(p)));
+            line[at] = Some(step.who);
+            for (p, b) in before.iter().enumerate() {
+                self.scores[p].sub(b);
+                self.scores[p].add(&GoScores::of_line(line, Player(p as u8)));
             }
         }
-        // log(format!("five: {}, block: {:?}, non-block:{:?}", self.scores[0].five,self.scores[0].block,self.scores[0].non_block));
-        // log(format!("five: {}, block: {:?}, non-block:{:?}", self.scores[1].five,self.scores[1].block,self.scores[1].non_block));
     }
 
-    fn get_pos_neighbor(&self, pos: &GoPos, piece: &GoPiece, level: u8) -> Vec<GoPos> {
-        let mut v = vec![];
-        for dir in Dir::DIRS {
-            let mut p = pos.clone();
-            p.dir_add(&dir);
-            if self.get_piece(&p) == *piece {
-                v.push(p);
-            }
+    /// 过pos沿dir方向的整条线，返回线上的格子、长度与pos在线上的下标
+    fn line_through(&self, pos: &GoPos, dir: &Dir) -> ([Option<Player>; 15], usize, usize) {
+        let (dx, dy) = dir.get_pos_offset();
+        let mut start = *pos;
+        let mut at = 0;
+        while let Some(p) = offset(&start, -dx, -dy) {
+            start = p;
+            at += 1;
         }
-        v
-    }
-
-    // pub(super) fn update_next_pieces(&mut self, pos: &GoPos) {
-    //     if !self.next_pieces.remove(pos) {
-    //         eprintln!("Error: GoBoard: pieces remove fault")
-    //     }
-    //     for i in self.get_pos_neighbor(pos, &GoPiece::None, 1) {
-    //         self.next_pieces.insert(i);
-    //     }
-    // }
-
-    pub(super) fn has_neighbor(&self, step: &GoStep) -> bool {
-        for dir in Dir::DIRS {
-            let mut p = step.pos.clone();
-            p.dir_add(&dir);
-            if self.get_piece(&p) == GoPiece::P(step.who) {
-                return true;
+        let mut line = [None; 15];
+        let mut len = 0;
+        let mut p = Some(start);
+        while let Some(q) = p {
+            if let GoPiece::P(x) = self.get_piece(&q) {
+                line[len] = Some(x);
             }
+            len += 1;
+            p = offset(&q, dx, dy);
         }
-        false
+        (line, len, at)
     }
 
-    pub(super) fn part_score(&self, step: &GoStep) -> i16 {
+    pub(super) fn part_score(&self, step: &GoStep) -> i32 {
         let scores = &self.scores;
         let me_idx = step.who.0 as usize;
 
@@ -244,31 +184,15 @@ impl GoPos {
         self.0 < 15 && self.1 < 15
     }
 
+    /// 沿dir走一格，出界时不动并返回false
     fn dir_add(&mut self, dir: &Dir) -> bool {
         let offset = dir.get_pos_offset();
-        // 简易情况
-        if self.0 > 0 && self.0 < 14 && self.1 > 0 && self.1 < 14 {
-            self.0 = ((self.0 as i8) + offset.0) as u8;
-            self.1 = ((self.1 as i8) + offset.1) as u8;
-            return true;
-        }
-
-        // 不合法情况过滤
-        if self.0 == 0 {
-            return !(*dir == Verti(false) || *dir == FSlash(false) || *dir == BSlash(false));
-        } else if self.0 == 14 {
-            return !(*dir == Verti(true) || *dir == FSlash(true) || *dir == BSlash(true));
-        }
-
-        if self.1 == 0 {
-            return !(*dir == Horiz(false) || *dir == FSlash(true) || *dir == BSlash(false));
-        } else if self.1 == 14 {
-            return !(*dir == Horiz(true) || *dir == FSlash(false) || *dir == BSlash(true));
+        let (x, y) = (self.0 as i8 + offset.0, self.1 as i8 + offset.1);
+        if !(0..15).contains(&x) || !(0..15).contains(&y) {
+            return false;
         }
-
-        self.0 = ((self.0 as i8) + offset.0) as u8;
-        self.1 = ((self.1 as i8) + offset.1) as u8;
-
+        self.0 = x as u8;
+        self.1 = y as u8;
         true
     }
 }
@@ -282,7 +206,7 @@ impl GoScores {
         }
     }
 
-    fn get(&self, flag: u8, block: bool) -> i16 {
+    fn get(&self, flag: u8, block: bool) -> i32 {
         if flag >= 5 {
             return Self::FIVE;
         }
@@ -313,28 +237,57 @@ impl GoScores {
         }
     }
 
-    fn remove(&mut self, flag: u8, block: bool) -> bool {
-        if (block && (flag < 2 || flag > 4)) || (!block && (flag < 1 || flag > 4)) {
-            return false;
+    /// 一条线上who的成绩。线端和对方棋子是墙，两墙之间放不下五子的连子不算；
+    /// 连子挨着墙算被封，五个以上算成五，单子不算
+    pub(super) fn of_line(line: &[Option<Player>], who: Player) -> Self {
+        let mut s = GoScores::new();
+        let mut start = 0;
+        while start < line.len() {
+            let end = (start..line.len()).find(|i| line[*i].is_some_and(|p| p != who)).unwrap_or(line.len());
+            if end - start >= 5 {
+                let mut i = start;
+                while i < end {
+                    if line[i].is_none() {
+                        i += 1;
+                        continue;
+                    }
+                    let run = (i..end).find(|j| line[*j].is_none()).unwrap_or(end);
+                    s.insert((run - i).min(5) as u8, i == start || run == end);
+                    i = run;
+                }
+            }
+            start = end + 1;
         }
+        s
+    }
 
-        if block && self.block[flag as usize - 2] > 0 {
-            self.block[flag as usize - 2] -= 1;
-        } else if !block && self.non_block[flag as usize - 1] > 0 {
-            self.non_block[flag as usize - 1] -= 1;
-        } else {
-            return false;
+    pub(super) fn add(&mut self, other: &GoScores) {
+        self.five += other.five;
+        for (a, b) in self.non_block.iter_mut().zip(other.non_block) {
+            *a += b;
+        }
+        for (a, b) in self.block.iter_mut().zip(other.block) {
+            *a += b;
+        }
+    }
+
+    fn sub(&mut self, other: &GoScores) {
+        self.five -= other.five;
+        for (a, b) in self.non_block.iter_mut().zip(other.non_block) {
+            *a -= b;
+        }
+        for (a, b) in self.block.iter_mut().zip(other.block) {
+            *a -= b;
         }
-        true
     }
 
-    pub(super) fn sum(&self) -> i16 {
-        let mut sum = (self.five as i16) * Self::FIVE;
+    pub(super) fn sum(&self) -> i32 {
+        let mut sum = (self.five as i32) * Self::FIVE;
         for i in 0..4 {
-            sum += (self.non_block[i] as i16) * Self::NON_BLOCK[i];
+            sum += (self.non_block[i] as i32) * Self::NON_BLOCK[i];
         }
         for i in 0..3 {
-            sum += (self.block[i] as i16) * Self::BLOCK[i];
+            sum += (self.block[i] as i32) * Self::BLOCK[i];
         }
         sum
     }
@@ -0,0 +1,215 @@
+use crate::base::Player;
+
+use super::{GoBoard, GoStep, GoPiece};
+
+// 连续冲四取胜（VCF）的求解，只看棋子分布，不用增量的评分
+
+type Grid = [[GoPiece; 15]; 15];
+type Point = (i8, i8);
+
+const DIRS: [(i8, i8); 4] = [(0, 1), (1, 0), (1, -1), (1, 1)];
+
+fn get(g: &Grid, x: i8, y: i8) -> Option<GoPiece> {
+    if (0..15).contains(&x) && (0..15).contains(&y) {
+        Some(g[x as usize][y as usize])
+    } else {
+        None
+    }
+}
+
+/// who在空位(x, y)落子能否成五（长连也算）
+fn is_five_point(g: &Grid, x: i8, y: i8, who: Player) -> bool {
+    if get(g, x, y) != Some(GoPiece::None) {
+        return false;
+    }
+    DIRS.iter().any(|(dx, dy)| {
+        let mut n = 1;
+        for s in [1, -1] {
+            let mut i = 1;
+            while get(g, x + dx * i * s, y + dy * i * s) == Some(GoPiece::P(who)) {
+                n += 1;
+                i += 1;
+            }
+        }
+        n >= 5
+    })
+}
+
+fn five_points(g: &Grid, who: Player) -> Vec<(i8, i8)> {
+    (0..15).flat_map(|x| (0..15).map(move |y| (x, y)))
+        .filter(|(x, y)| is_five_point(g, *x, *y, who))
+        .collect()
+}
+
+/// 刚在(x, y)落子后，经过它的四条线上who的成五点
+fn five_points_through(g: &Grid, x: i8, y: i8, who: Player) -> Vec<(i8, i8)> {
+    let mut v = vec![];
+    for (dx, dy) in DIRS {
+        for i in (-4..=4).filter(|i| *i != 0) {
+            let p = (x + dx * i, y + dy * i);
+            if is_five_point(g, p.0, p.1, who) && !v.contains(&p) {
+                v.push(p);
+            }
+        }
+    }
+    v
+}
+
+/// who所有冲四（落下后有成五点）的走法及对应的成五点，能直接成两个五点的排在前面
+fn four_moves(g: &mut Grid, who: Player) -> Vec<(Point, Vec<Point>)> {
+    let mut v = vec![];
+    for x in 0..15 {
+        for y in 0..15 {
+            if g[x as usize][y as usize] != GoPiece::None {
+                continue;
+            }
+            g[x as usize][y as usize] = GoPiece::P(who);
+            let points = five_points_through(g, x, y, who);
+            g[x as usize][y as usize] = GoPiece::None;
+            if !points.is_empty() {
+                v.push(((x, y), points));
+            }
+        }
+    }
+    v.sort_by_key(|m| std::cmp::Reverse(m.1.len()));
+    v
+}
+
+fn step(p: (i8, i8), who: Player) -> GoStep {
+    GoStep::new(p.0 as u8, p.1 as u8, who)
+}
+
+/// attacker先走，最多fours步冲四后成五的一种走法，双方交替，最后一步成五
+fn search(g: &mut Grid, attacker: Player, fours: u8, first: Option<(i8, i8)>) -> Option<Vec<GoStep>> {
+    let defender = attacker.rev();
+    if first.is_none() {
+        if let Some(p) = five_points(g, attacker).first() {
+            return Some(vec![step(*p, attacker)]);
+        }
+    }
+    if fours == 0 {
+        return None;
+    }
+    // 对方有四时只能先挡，并且挡的这步也要是冲四
+    let counter = five_points(g, defender);
+    if counter.len() > 1 {
+        return None;
+    }
+    for (m, points) in four_moves(g, attacker) {
+        if counter.first().is_some_and(|c| *c != m) || first.is_some_and(|f| f != m) {
+            continue;
+        }
+        let d = points[0];
+        if points.len() > 1 {
+            return Some(vec![step(m, attacker), step(d, defender), step(points[1], attacker)]);
+        }
+        g[m.0 as usize][m.1 as usize] = GoPiece::P(attacker);
+        g[d.0 as usize][d.1 as usize] = GoPiece::P(defender);
+        let rest = search(g, attacker, fours - 1, None);
+        g[m.0 as usize][m.1 as usize] = GoPiece::None;
+        g[d.0 as usize][d.1 as usize] = GoPiece::None;
+        if let Some(rest) = rest {
+            let mut v = vec![step(m, attacker), step(d, defender)];
+            v.extend(rest);
+            return Some(v);
+        }
+    }
+    None
+}
+
+/// attacker先走，冲四不超过fours步取胜的最短走法（不含直接成五时为0步）
+pub fn solve_vcf(board: &GoBoard, attacker: Player, fours: u8) -> Option<Vec<GoStep>> {
+    let mut g = board.pieces;
+    (0..=fours).find_map(|n| search(&mut g, attacker, n, None))
+}
+
+/// 冲四不超过fours步取胜的所有第一步（都要是冲四）
+pub fn winning_fours(board: &GoBoard, attacker: Player, fours: u8) -> Vec<GoStep> {
+    let mut g = board.pieces;
+    four_moves(&mut g, attacker).into_iter()
+        .filter(|(m, _)| search(&mut g, attacker, fours, Some(*m)).is_some())
+        .map(|(m, _)| step(m, attacker))
+        .collect()
+}
+
+/// attacker走完后防守方的应对：自己能成五就成五，否则挡住attacker的成五点。
+/// attacker没有成五点（这步不是冲四）时为None
+pub fn forced_reply(board: &GoBoard, attacker: Player) -> Option<GoStep> {
+    let g = &board.pieces;
+    let threats = five_points(g, attacker);
+    let own = five_points(g, attacker.rev());
+    threats.first()?;
+    own.first().or(threats.first()).map(|p| step(*p, attacker.rev()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{base::{Board, Step}, position};
+
+    fn board(text: &str) -> (GoBoard, Player) {
+        position::parse(text, &GoBoard::new()).unwrap()
+    }
+
+    /// 按走法落子，最后一步之后进攻方获胜
+    fn check_win(b: &GoBoard, attacker: Player, steps: &[GoStep]) {
+        let mut b = b.clone();
+        for s in steps {
+            assert!(b.put(*s));
+        }
+        assert!(b.over() == crate::base::GameState::Over(crate::base::OutCome::Winer(attacker)));
+    }
+
+    #[test]
+    fn immediate_five_and_open_four() {
+        let (b, p) = board("/7/.oooo/ o");
+        let v = solve_vcf(&b, p, 3).unwrap();
+        assert_eq!(v.len(), 1);
+        check_win(&b, p, &v);
+
+        // 活三冲成活四：一步冲四后挡不住
+        let (b, p) = board("/7/..ooo/ o");
+        let v = solve_vcf(&b, p, 3).unwrap();
+        assert_eq!(v.len(), 3);
+        check_win(&b, p, &v);
+    }
+
+    #[test]
+    fn double_four_is_unique() {
+        // 横竖两个眠三交叉，(7,8)一步成四四
+        let (b, p) = board("/15/15/8x6/8o6/8o6/8o6/3xooo8 o");
+        let v = solve_vcf(&b, p, 3).unwrap();
+        assert_eq!(v.len(), 3);
+        check_win(&b, p, &v);
+        let first: Vec<_> = winning_fours(&b, p, 1).iter().map(|s| s.to_pos()).collect();
+        assert_eq!(first, [(7, 8)]);
+    }
+
+    #[test]
+    fn chain_of_fours() {
+        // 先在(6,8)冲四，逼对方挡(6,9)，竖线成三后(7,8)成四四
+        let (b, p) = board("/15/15/8xxx4/8o6/8o6/4xooo7/3xooo8/5x9 o");
+        assert!(solve_vcf(&b, p, 1).is_none());
+        let v = solve_vcf(&b, p, 3).unwrap();
+        assert_eq!(v.len(), 5);
+        check_win(&b, p, &v);
+    }
+
+    #[test]
+    fn defender_counter_four() {
+        // 对方已有冲四，进攻方不挡就输，挡的点又不能成四时没有VCF
+        let (b, p) = board("/7/..ooo/15/15/15/15/15/15/15/15/15/15/15/xxxx o");
+        assert!(solve_vcf(&b, p, 4).is_none());
+        // 防守方的应对是自己成五
+        let mut b2 = b.clone();
+        b2.put(GoStep::new_put_step((2, 5), p));
+        let r = forced_reply(&b2, p).unwrap();
+        assert_eq!(r.to_pos(), (14, 4));
+    }
+
+    #[test]
+    fn not_a_four_has_no_forced_reply() {
+        let (b, p) = board("/7/..oo/ o");
+        assert!(forced_reply(&b, p).is_none());
+    }
+}
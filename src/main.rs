@@ -1,34 +1,534 @@
 mod base;
 mod display;
 mod ai;
+mod solver;
+mod book;
+mod mcts;
+mod record;
+mod position;
+mod puzzle;
+mod clock;
+mod net;
+mod agent;
+mod arena;
 
 mod tic_tac_toe;
 mod gobang;
 mod goT;
 
+use std::{collections::{HashMap, hash_map::Entry}, env, fs, io::{self, IsTerminal}, thread, time::{Duration, Instant}};
+
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
 use base::*;
-use display::log;
 
-use tic_tac_toe::{TTTBoard, TTTStep};
-use gobang::{GoBoard, GoStep};
-use goT::{GoTBoard, GoTStep};
+use tic_tac_toe::TTTBoard;
+use gobang::{GoBoard, GoStep, BitBoard};
+use goT::GoTBoard;
+use solver::Tablebase;
+use book::Book;
+use ai::{AI, Level, Style, Engine, SearchCtx};
+use position::SetUp;
+use display::tui::TuiOptions;
+use clock::TimeControl;
+use net::{Peer, Message};
+use agent::{Agent, Human, Computer, Remote, External, Protocol};
+use solver::Oracle;
+
+const BOOK_PATH: &str = "gobang.book";
 
+/// 用法：
+///   rustbang [ttt|gobang|gobang-bit|got] [难度] [风格]   开始一局游戏，默认got；gobang-bit为位棋盘实现的五子棋
+///     got不给难度时双方都是本地的人
+///     难度：beginner|easy|normal|full，风格：balanced|aggressive|defensive
+///     --threads <n>  搜索线程数，默认为CPU核数
+///     --engine alphabeta|mcts|mcts-heuristic  电脑的搜索算法，默认alphabeta
+///     --exploration <c>  蒙特卡洛的探索系数
+///     --radius <r>  五子棋候选点到已有棋子的最大距离，默认1
+///     --validate  五子棋每步后校验增量成绩，不一致时写日志
+///     --position <局面>  从摆好的局面开始，格式见position.rs，如"o.x/1o1/3 x"
+///     --clock <用时>  双方计时，如5m、5m+3s（加秒）、10m/30s*3（读秒），格式见clock.rs
+///     --host <地址>  在地址（如0.0.0.0:7777）上等对方连入，本方先走，游戏与设置由本方定
+///     --join <地址>  连到对方，游戏、局面与用时按对方的设置，协议见net.rs
+///     --external <命令>  对手换成外部引擎，协议见agent/external.rs
+///     --piskvork  外部引擎用Piskvork协议，只用于五子棋
+///     --side o|x  本方执哪一方，默认o
+///     --hint-time <秒>  提示的思考时间，默认2，分析用两倍
+///     --hint-top <n>  提示几个候选，默认3，最多9
+///     --no-threats  不标出对方下一步就能赢的点
+///     --text  按行的文字界面，输入坐标走棋，见display/text.rs；输入不是终端（如管道）时自动使用
+///   rustbang book records <棋谱文件> <输出>   从棋谱建五子棋开局库
+///   rustbang book selfplay <局数> <输出>      自对弈建五子棋开局库
+///   rustbang bench [深度]                     比较五子棋两种棋盘表示的速度，以及GoBoard复制与play/undo的开销
+///   rustbang check [局数] [种子]              随机对局（含悔棋）校验五子棋增量成绩，报告第一个不一致的局面
+///   rustbang puzzle <题目文件>                 做五子棋连续冲四题，格式见puzzle.rs
+///   rustbang puzzle gen <局数> <最多冲四步数> <输出>   自对弈出题
+///   rustbang match <ttt|gobang|got> <局数> <甲> <乙>  不用界面对局，交换先后手，统计胜负
+///     座位：ai或ai:<难度>为内置电脑，ext:<命令>为文本协议的外部引擎，piskvork:<命令>为Piskvork引擎；
+///     --clock、--position、--threads、--engine同上
 fn main() {
-    // let mut game = Game::new(TTTBoard::new(), [Role::Hum, Role::Com], GameType::Put);
-    // let mut game = Game::new(
-    //     GoBoard::new(),
-    //     [Role::Hum, Role::Com],
-    //     GameType::Put,
-    // );
-    let mut game = Game::new(
-        GoTBoard::new(),
-        [Role::Hum, Role::Hum],
-        GameType::Move
-    );
-
-    display::tui::tui_main(&mut game);
-    // game.step(GoStep::new(7, 7, game.curr_player));
-    // game.step(ai::get_next_best_step(&game.board, game.curr_player).unwrap());
-
-    // println!("hello");
+    let mut args: Vec<String> = env::args().collect();
+    let threads = take_opt(&mut args, "--threads").and_then(|s| s.parse().ok())
+        .unwrap_or_else(|| thread::available_parallelism().map(|n| n.get().min(u8::MAX as usize) as u8).unwrap_or(1));
+    let mut engine = take_opt(&mut args, "--engine").and_then(|s| Engine::from_name(&s)).unwrap_or(Engine::AlphaBeta);
+    if let (Engine::Mcts(config), Some(c)) = (&mut engine, take_opt(&mut args, "--exploration")) {
+        config.exploration = c.parse().unwrap_or_else(|_| {
+            eprintln!("bad exploration: {}", c);
+            std::process::exit(1);
+        });
+    }
+    let radius = take_opt(&mut args, "--radius").and_then(|s| s.parse().ok());
+    let validate = take_flag(&mut args, "--validate");
+    let text = take_flag(&mut args, "--text") || !io::stdin().is_terminal();
+    let mut position = take_opt(&mut args, "--position");
+    let mut clock = take_opt(&mut args, "--clock");
+    let external = take_opt(&mut args, "--external");
+    let protocol = if take_flag(&mut args, "--piskvork") { Protocol::Piskvork } else { Protocol::Text };
+    let side = take_opt(&mut args, "--side");
+    let opts = take_tui_options(&mut args, threads);
+    let (mut peer, mut local) = connect(&mut args, &mut position, &mut clock);
+    if protocol == Protocol::Piskvork && !matches!(args.get(1).map(|s| s.as_str()), Some("gobang" | "gobang-bit")) {
+        eprintln!("--piskvork is only for gobang");
+        std::process::exit(1);
+    }
+    if side.as_deref() == Some("x") && peer.is_none() {
+        local = Player(1);
+    }
+    let clock = clock.map(|s| TimeControl::from_name(&s).unwrap_or_else(|| {
+        eprintln!("bad clock: {}", s);
+        std::process::exit(1);
+    }));
+    let (level, style) = level_and_style(&args);
+    let level = level.with_style(style).with_threads(threads).with_engine(engine);
+
+    match args.get(1).map(|s| s.as_str()) {
+        Some("book") => build_book(&args[2..]),
+        Some("bench") => {
+            let depth = args.get(2).and_then(|s| s.parse().ok()).unwrap_or(6);
+            bench::<GoBoard>("GoBoard", depth);
+            bench::<BitBoard>("BitBoard", depth);
+            bench_undo();
+        },
+        Some("check") => {
+            let games = args.get(2).and_then(|s| s.parse().ok()).unwrap_or(1000);
+            let seed = args.get(3).and_then(|s| s.parse().ok()).unwrap_or(0);
+            match gobang::find_divergence(games, &mut StdRng::seed_from_u64(seed)) {
+                Some(d) => println!("{}", d),
+                None => println!("{}局没有发现不一致", games),
+            }
+        },
+        Some("puzzle") => puzzle(&args[2..]),
+        Some("match") => run_match(&args[2..], level, &position, clock, radius),
+        Some("ttt") => {
+            let mut game = new_game(TTTBoard::new(), GameType::Put, &position, clock);
+            let table = Tablebase::solve(&game.board, game.curr_player);
+            let seats = seats(level, Some(&table), &mut peer, &external, protocol, local);
+            play(&mut game, seats, Some(&table), opts, text);
+        },
+        Some("gobang") => {
+            let mut board = radius.map_or_else(GoBoard::new, GoBoard::with_radius);
+            if validate {
+                board = board.with_validation();
+            }
+            let mut game = new_game(board, GameType::Put, &position, clock);
+            let book = Book::load(BOOK_PATH).ok();
+            let book = book.as_ref().map(|b| b as _);
+            play(&mut game, seats(level, book, &mut peer, &external, protocol, local), book, opts, text);
+        },
+        Some("gobang-bit") => {
+            let mut game = new_game(BitBoard::new(), GameType::Put, &position, clock);
+            let book = Book::load(BOOK_PATH).ok();
+            let book = book.as_ref().map(|b| b as _);
+            play(&mut game, seats(level, book, &mut peer, &external, protocol, local), book, opts, text);
+        },
+        _ => {
+            let mut game = new_game(GoTBoard::new(), GameType::Move, &position, clock);
+            // 文件里只有从开局出发可达的局面，摆出的局面单独求解
+            let table = match position {
+                Some(_) => Tablebase::solve(&game.board, game.curr_player),
+                None => Tablebase::load_or_solve("goT.tb", &game.board, game.curr_player),
+            };
+            let seats: [Box<dyn Agent<_, _>>; 2] = if args.get(2).is_none() && peer.is_none() && external.is_none() {
+                [Box::new(Human::new()), Box::new(Human::new())]
+            } else {
+                seats(level, Some(&table), &mut peer, &external, protocol, local)
+            };
+            play(&mut game, seats, Some(&table), opts, text);
+        },
+    }
+}
+
+/// 座位就绪后开始对局，text为真时用按行的文字界面
+fn play<B, S>(game: &mut Game<B, S>, seats: [Box<dyn Agent<B, S> + '_>; 2], oracle: Option<&dyn Oracle<B, S>>,
+    opts: TuiOptions, text: bool) where
+    B: SetUp<S> + display::Display + AI<S>,
+    S: Step + std::fmt::Debug
+{
+    if text {
+        display::text::text_main(game, seats, oracle, opts, io::stdin().lock(), io::stdout());
+    } else {
+        display::tui::tui_main(game, seats, oracle, opts);
+    }
+}
+
+/// --host时等对方连入并发出设置，--join时连到对方并按收到的设置改写游戏、局面与用时。
+/// 返回连接与本方执哪一方
+fn connect(args: &mut Vec<String>, position: &mut Option<String>, clock: &mut Option<String>) -> (Option<Peer>, Player) {
+    let host = take_opt(args, "--host");
+    let join = take_opt(args, "--join");
+    let fail = |e: String| -> ! {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    };
+    if let Some(addr) = host {
+        if clock.as_deref().is_some_and(|c| TimeControl::from_name(c).is_none()) {
+            fail(format!("bad clock: {}", clock.as_deref().unwrap_or_default()));
+        }
+        println!("等待对方连接{}...", addr);
+        let peer = Peer::host(&addr).unwrap_or_else(|e| fail(format!("{}: {}", addr, e)));
+        let game = args.get(1).cloned().unwrap_or_else(|| "got".to_string());
+        let setup = Message::Setup { game, host: Player(0), clock: clock.clone(), position: position.clone() };
+        peer.send(&setup).unwrap_or_else(|e| fail(e.to_string()));
+        return (Some(peer), Player(0));
+    }
+    let Some(addr) = join else {
+        return (None, Player(0));
+    };
+    let peer = Peer::join(&addr).unwrap_or_else(|e| fail(format!("{}: {}", addr, e)));
+    match peer.recv() {
+        Ok(Message::Setup { game, host, clock: c, position: p }) => {
+            // 难度、风格对联网对局没有意义，只保留游戏名
+            args.truncate(1);
+            args.push(game);
+            *position = p;
+            *clock = c;
+            (Some(peer), host.rev())
+        },
+        Ok(m) => fail(format!("unexpected message: {}", m.to_line())),
+        Err(e) => fail(e.to_string()),
+    }
+}
+
+/// local执的一方为人；另一方联网时为对方，有外部引擎时为引擎，否则为电脑
+fn seats<'a, B: AI<S> + SetUp<S> + 'a, S: Step + 'a>(level: Level, oracle: Option<&'a dyn Oracle<B, S>>,
+    peer: &mut Option<Peer>, external: &Option<String>, protocol: Protocol, local: Player) -> [Box<dyn Agent<B, S> + 'a>; 2] {
+
+    let other: Box<dyn Agent<B, S>> = match (peer.take(), external) {
+        (Some(p), _) => Box::new(Remote::new(p)),
+        (None, Some(command)) => Box::new(External::new(command, protocol, agent::MOVE_TIME)),
+        (None, None) => Box::new(Computer::new(level, oracle)),
+    };
+    let human: Box<dyn Agent<B, S>> = Box::new(Human::new());
+    if local.0 == 0 { [human, other] } else { [other, human] }
+}
+
+/// 取出提示相关的参数
+fn take_tui_options(args: &mut Vec<String>, threads: u8) -> TuiOptions {
+    let mut opts = TuiOptions { threads, ..TuiOptions::default() };
+    if let Some(t) = take_opt(args, "--hint-time").and_then(|s| s.parse().ok()) {
+        opts.hint_time = Duration::from_secs_f64(t);
+    }
+    if let Some(n) = take_opt(args, "--hint-top").and_then(|s| s.parse().ok()) {
+        opts.hint_top = n;
+    }
+    opts.threat_warning = !take_flag(args, "--no-threats");
+    opts
+}
+
+/// 游戏名后面的难度与风格，须在取出所有--参数之后调用
+fn level_and_style(args: &[String]) -> (Level, Style) {
+    let level = args.get(2).and_then(|s| Level::from_name(s)).unwrap_or_default();
+    let style = match args.get(3).map(|s| s.as_str()) {
+        Some("aggressive") => Style::Aggressive,
+        Some("defensive") => Style::Defensive,
+        _ => Style::Balanced,
+    };
+    (level, style)
+}
+
+/// 取出形如`--name value`的参数
+fn take_opt(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let i = args.iter().position(|a| a == name)?;
+    let v = args.get(i + 1).cloned();
+    args.drain(i..(i + 2).min(args.len()));
+    v
+}
+
+/// 有--position时从摆好的局面开始，格式不对就退出；有--clock时计时
+fn new_game<B: SetUp<S>, S: Step>(board: B, game_type: GameType,
+    position: &Option<String>, clock: Option<TimeControl>) -> Game<B, S> {
+
+    let game = match position {
+        None => Game::new(board, game_type),
+        Some(text) => match position::parse(text, &board) {
+            Some((board, next)) => Game::new(board, game_type).with_next_player(next),
+            None => {
+                eprintln!("bad position: {}", text);
+                std::process::exit(1);
+            },
+        },
+    };
+    match clock {
+        Some(c) => game.with_clock(c),
+        None => game,
+    }
+}
+
+/// 取出开关参数
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    match args.iter().position(|a| a == name) {
+        Some(i) => {
+            args.remove(i);
+            true
+        },
+        None => false,
+    }
+}
+
+/// 随机对局测落子与生成候选的速度，再在几个局面上测搜索
+fn bench<B: AI<GoStep>>(name: &str, depth: u8) {
+    const GAMES: usize = 200;
+    const POSITIONS: [&str; 3] = ["7,7 7,8 8,8", "7,7 7,8 8,8 6,6 8,7 8,9 9,8", "7,7 8,8 7,8 7,9 6,8 5,8 6,7 8,9"];
+
+    // 落子与评分：双方随机落子到分出胜负
+    let mut rng = StdRng::seed_from_u64(1);
+    let mut puts = 0;
+    let t = Instant::now();
+    for _ in 0..GAMES {
+        let mut board = B::new();
+        let mut player = Player(0);
+        while board.over() == GameState::Running {
+            let pos = (rng.gen_range(0..15), rng.gen_range(0..15));
+            if board.put(GoStep::new_put_step(pos, player)) {
+                board.score(player);
+                puts += 1;
+                player = player.rev();
+            }
+        }
+    }
+    let elapsed = t.elapsed();
+    println!("{}: put+score {:.2} us/move ({} moves)", name, elapsed.as_micros() as f64 / puts as f64, puts);
+
+    // 生成候选：每步在候选中随机选
+    let mut gens = 0;
+    let t = Instant::now();
+    for _ in 0..GAMES {
+        let mut board = B::new();
+        let mut player = Player(0);
+        while board.over() == GameState::Running {
+            let steps = board.get_possible_steps(player, u8::MAX);
+            gens += 1;
+            if steps.is_empty() {
+                break;
+            }
+            board.put(steps[rng.gen_range(0..steps.len())]);
+            player = player.rev();
+        }
+    }
+    let elapsed = t.elapsed();
+    println!("{}: put+generate {:.2} us/move ({} moves)", name, elapsed.as_micros() as f64 / gens as f64, gens);
+
+    for line in POSITIONS {
+        let start = B::new();
+        let mut board = start.clone();
+        let steps = record::parse_game(line, &start).expect("position");
+        for s in &steps {
+            board.put(*s);
+        }
+        let player = Player((steps.len() % 2) as u8);
+        let level = Level { depth, ..Level::FULL };
+        let t = Instant::now();
+        let a = ai::analyze(&board, player, &level, SearchCtx::new(None));
+        let elapsed = t.elapsed();
+        println!("{}: [{}] depth {} nodes {} best {} in {:?} ({:.2} us/node)", name, line, depth, a.nodes,
+            record::step_to_string(&a.moves[0].step), elapsed, elapsed.as_micros() as f64 / a.nodes as f64);
+    }
+}
+
+/// 搜索在每个节点复制棋盘（copy_put），与GoBoard的play/undo比较每一步的开销
+fn bench_undo() {
+    const GAMES: usize = 200;
+    let mut rng = StdRng::seed_from_u64(1);
+    let (mut copies, mut plays) = (Duration::ZERO, Duration::ZERO);
+    let mut n = 0;
+    for _ in 0..GAMES {
+        let mut board = GoBoard::new();
+        let mut player = Player(0);
+        while board.over() == GameState::Running {
+            let steps = board.get_possible_steps(player, u8::MAX);
+            if steps.is_empty() {
+                break;
+            }
+            let t = Instant::now();
+            for s in &steps {
+                std::hint::black_box(board.copy_put(*s));
+            }
+            copies += t.elapsed();
+            let t = Instant::now();
+            for s in &steps {
+                let u = board.play(*s).expect("legal step");
+                board.undo(std::hint::black_box(u));
+            }
+            plays += t.elapsed();
+            n += steps.len();
+            board.put(steps[rng.gen_range(0..steps.len())]);
+            player = player.rev();
+        }
+    }
+    println!("GoBoard: copy_put {:.2} us/step, play+undo {:.2} us/step ({} steps)",
+        copies.as_micros() as f64 / n as f64, plays.as_micros() as f64 / n as f64, n);
+}
+
+fn puzzle(args: &[String]) {
+    match (args.first().map(|s| s.as_str()), args.get(1), args.get(2), args.get(3)) {
+        (Some("gen"), Some(games), Some(fours), Some(out)) => {
+            let games = games.parse().expect("games");
+            let fours = fours.parse().expect("fours");
+            let set = puzzle::generate(games, fours, &mut rand::thread_rng());
+            set.save(out).expect("save puzzles");
+            println!("{} puzzles", set.puzzles.len());
+        },
+        (Some(file), None, ..) => match puzzle::PuzzleSet::load(file) {
+            Ok(mut set) => display::tui::tui_puzzle(&mut set, file),
+            Err(e) => eprintln!("{}: {}", file, e),
+        },
+        _ => eprintln!("usage: rustbang puzzle <file> | rustbang puzzle gen <games> <max fours> <out>"),
+    }
+}
+
+/// 按`ai`、`ai:<难度>`、`ext:<命令>`、`piskvork:<命令>`建一个座位
+fn seat<'a, B: AI<S> + SetUp<S> + 'a, S: Step + 'a>(spec: &str, level: Level,
+    oracle: Option<&'a dyn Oracle<B, S>>, piskvork: bool) -> Option<Box<dyn Agent<B, S> + 'a>> {
+
+    let (kind, rest) = spec.split_once(':').unwrap_or((spec, ""));
+    Some(match kind {
+        "ai" if rest.is_empty() => Box::new(Computer::new(level, oracle)),
+        "ai" => {
+            let l = Level::from_name(rest)?;
+            Box::new(Computer::new(l.with_style(level.style).with_threads(level.threads).with_engine(level.engine), oracle))
+        },
+        "ext" if !rest.is_empty() => Box::new(External::new(rest, Protocol::Text, agent::MOVE_TIME)),
+        "piskvork" if piskvork && !rest.is_empty() => Box::new(External::new(rest, Protocol::Piskvork, agent::MOVE_TIME)),
+        _ => return None,
+    })
+}
+
+fn run_match(args: &[String], level: Level, position: &Option<String>, clock: Option<TimeControl>, radius: Option<u8>) {
+    let usage = || eprintln!("usage: rustbang match <ttt|gobang|got> <games> <seat> <seat>, seat: ai[:level] | ext:<command> | piskvork:<command>");
+    let (Some(name), Some(Ok(games)), Some(a), Some(b)) =
+        (args.first(), args.get(1).map(|s| s.parse::<usize>()), args.get(2), args.get(3)) else {
+        return usage();
+    };
+    let specs = [a.as_str(), b.as_str()];
+    let ok = match name.as_str() {
+        "ttt" => {
+            let game = new_game(TTTBoard::new(), GameType::Put, position, clock);
+            let table = Tablebase::solve(&game.board, game.curr_player);
+            report_match(games, || new_game(TTTBoard::new(), GameType::Put, position, clock), specs, level, Some(&table), false)
+        },
+        "gobang" => {
+            let book = Book::load(BOOK_PATH).ok();
+            let book = book.as_ref().map(|b| b as _);
+            let board = || radius.map_or_else(GoBoard::new, GoBoard::with_radius);
+            report_match(games, || new_game(board(), GameType::Put, position, clock), specs, level, book, true)
+        },
+        "got" => {
+            let game = new_game(GoTBoard::new(), GameType::Move, position, clock);
+            let table = match position {
+                Some(_) => Tablebase::solve(&game.board, game.curr_player),
+                None => Tablebase::load_or_solve("goT.tb", &game.board, game.curr_player),
+            };
+            report_match(games, || new_game(GoTBoard::new(), GameType::Move, position, clock), specs, level, Some(&table), false)
+        },
+        _ => false,
+    };
+    if !ok {
+        usage();
+    }
+}
+
+/// 每局打印结果与棋谱，最后打印甲、乙的战绩。座位写法不对时返回false
+fn report_match<'a, B: AI<S> + SetUp<S> + Symmetry<S> + 'a, S: Step + 'a>(games: usize, new_game: impl FnMut() -> Game<B, S>,
+    specs: [&str; 2], level: Level, oracle: Option<&'a dyn Oracle<B, S>>, piskvork: bool) -> bool {
+
+    let (Some(a), Some(b)) = (seat(specs[0], level, oracle, piskvork), seat(specs[1], level, oracle, piskvork)) else {
+        return false;
+    };
+    let names = [a.name(), b.name()];
+    // 对局key到局号，确定性的引擎常常重复下出同一局
+    let mut seen = HashMap::new();
+    let tally = arena::run(games, new_game, [a, b], |i, game, first| {
+        let result = match game.state {
+            GameState::Over(OutCome::Winer(p)) => format!("{}胜", names[(p != first) as usize]),
+            GameState::Over(OutCome::Timeout(p)) => format!("{}超时", names[(p != first) as usize]),
+            _ => "和".to_string(),
+        };
+        let same = match seen.entry(game.record_key()) {
+            Entry::Occupied(e) => format!("（同第{}局）", e.get() + 1),
+            Entry::Vacant(e) => {
+                e.insert(i);
+                String::new()
+            },
+        };
+        println!("{}: {}执先 {}{} | {}", i + 1, names[(first.0 != 0) as usize], result, same, record::game_to_string(&game.hist_steps));
+    });
+    println!("{} 胜{} 负{} 和{}", names[0], tally.wins[0], tally.wins[1], tally.draws);
+    true
+}
+
+fn build_book(args: &[String]) {
+    const MAX_PLY: usize = 12;
+    let start = GoBoard::new();
+    let mut book = Book::new();
+    match (args.first().map(|s| s.as_str()), args.get(1), args.get(2)) {
+        (Some("records"), Some(file), Some(out)) => {
+            let text = fs::read_to_string(file).expect("read records");
+            let mut games = vec![];
+            for (i, line) in text.lines().enumerate() {
+                match record::parse_game(line, &start) {
+                    Some(steps) => games.push(steps),
+                    None => eprintln!("line {}: bad record", i + 1),
+                }
+            }
+            let dups = record::dedup(&start, &mut games);
+            if dups > 0 {
+                println!("{} duplicate records skipped", dups);
+            }
+            for steps in &games {
+                book.add_record(&start, steps, MAX_PLY);
+            }
+            book.save(out).expect("save book");
+        },
+        (Some("selfplay"), Some(games), Some(out)) => {
+            let games = games.parse().expect("games");
+            let n = book.self_play(&start, games, 2, MAX_PLY, &book::SELF_PLAY_LEVEL, &mut rand::thread_rng());
+            println!("{} distinct games", n);
+            book.save(out).expect("save book");
+        },
+        _ => {
+            eprintln!("usage: rustbang book records <file> <out> | rustbang book selfplay <games> <out>");
+            return;
+        },
+    }
+    println!("{} positions", book.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn options_before_level_and_style() {
+        let mut a = args("rustbang gobang --hint-top 5 --no-threats easy --hint-time 0.5 aggressive");
+        let opts = take_tui_options(&mut a, 2);
+        let (level, style) = level_and_style(&a);
+        assert_eq!(level, Level::EASY);
+        assert_eq!(style, Style::Aggressive);
+        assert_eq!((opts.hint_top, opts.threat_warning, opts.threads), (5, false, 2));
+        assert_eq!(opts.hint_time, Duration::from_millis(500));
+    }
 }
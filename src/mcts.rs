@@ -0,0 +1,304 @@
+use std::time::Instant;
+
+use rand::{Rng, seq::SliceRandom};
+
+use crate::{base::{Player, Step, Game, GameState, OutCome}, ai::{AI, Level}};
+
+/// 模拟时的走法
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Playout {
+    /// 在候选中随机选
+    Random,
+    /// 走完后评分最高的一步，同分随机
+    Heuristic,
+}
+
+/// 蒙特卡洛树搜索的参数
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct MctsConfig {
+    /// UCT公式中的探索系数
+    pub exploration: f32,
+    /// 没有时间限制时的模拟次数
+    pub iterations: u32,
+    pub playout: Playout,
+    /// 模拟超过这么多步仍未结束时，按局面评分判胜负
+    pub max_playout: u16,
+}
+
+impl MctsConfig {
+    pub const DEFAULT: MctsConfig = MctsConfig {
+        exploration: std::f32::consts::SQRT_2,
+        iterations: 3000,
+        playout: Playout::Random,
+        max_playout: 60,
+    };
+}
+
+impl Default for MctsConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+struct Node<S> {
+    step: Option<S>,
+    /// 到达此节点后的行棋方
+    player: Player,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    /// 还没展开的步子，倒序存放，先展开生成顺序靠前的
+    untried: Vec<S>,
+    visits: u32,
+    /// 对走出step的一方而言的累计收益，胜1和0.5负0
+    reward: f32,
+}
+
+fn same_step<S: Step>(a: &S, b: &S) -> bool {
+    a.origin_pos() == b.origin_pos() && a.to_pos() == b.to_pos()
+}
+
+/// 蒙特卡洛树搜索（UCT），每个电脑座位持有一棵，走棋之间保留对应子树
+pub struct Mcts<B, S> {
+    nodes: Vec<Node<S>>,
+    /// 根节点的局面
+    board: Option<B>,
+    /// 根节点是对局中的第几步
+    ply: usize,
+}
+
+impl<B: AI<S>, S: Step> Mcts<B, S> {
+    pub fn new() -> Self {
+        Mcts { nodes: vec![], board: None, ply: 0 }
+    }
+
+    fn new_node(board: &B, player: Player, step: Option<S>, parent: Option<usize>) -> Node<S> {
+        let mut untried = match board.over() {
+            GameState::Running => board.get_possible_steps(player, u8::MAX),
+            GameState::Over(_) => vec![],
+        };
+        untried.reverse();
+        Node { step, player, parent, children: vec![], untried, visits: 0, reward: 0.0 }
+    }
+
+    fn reset(&mut self, board: &B, player: Player, ply: usize) {
+        self.nodes = vec![Self::new_node(board, player, None, None)];
+        self.board = Some(board.clone());
+        self.ply = ply;
+    }
+
+    /// 对局前进后，沿已走的步子找到对应子树作为新根；对不上时重建
+    fn sync(&mut self, game: &Game<B, S>) {
+        let len = game.hist_steps.len();
+        if let (Some(board), true) = (self.board.as_ref(), len >= self.ply) {
+            let mut board = board.clone();
+            let mut idx = Some(0);
+            for s in &game.hist_steps[self.ply..] {
+                idx = idx.and_then(|i| {
+                    self.nodes[i].children.iter().copied()
+                        .find(|c| same_step(self.nodes[*c].step.as_ref().unwrap(), s))
+                });
+                if !board.put(*s) {
+                    idx = None;
+                    break;
+                }
+            }
+            if let Some(i) = idx {
+                if board.key() == game.board.key() && self.nodes[i].player == game.curr_player {
+                    self.reroot(i);
+                    self.board = Some(board);
+                    self.ply = len;
+                    return;
+                }
+            }
+        }
+        self.reset(&game.board, game.curr_player, len);
+    }
+
+    /// 只保留以root为根的子树
+    fn reroot(&mut self, root: usize) {
+        if root == 0 {
+            return;
+        }
+        let mut old: Vec<Option<Node<S>>> = std::mem::take(&mut self.nodes).into_iter().map(Some).collect();
+        let mut queue = vec![(root, None)];
+        while let Some((i, parent)) = queue.pop() {
+            let mut node = old[i].take().unwrap();
+            let idx = self.nodes.len();
+            node.parent = parent;
+            queue.extend(node.children.drain(..).map(|c| (c, Some(idx))));
+            self.nodes.push(node);
+            if let Some(p) = parent {
+                self.nodes[p].children.push(idx);
+            }
+        }
+    }
+
+    /// 对局中使用，按难度的时间限制或配置的次数搜索
+    pub fn game_best_step<R: Rng>(&mut self, game: &Game<B, S>, level: &Level,
+        config: &MctsConfig, rng: &mut R) -> Option<S> {
+
+        self.sync(game);
+        self.run(level, config, rng);
+        self.best_step()
+    }
+
+    fn run<R: Rng>(&mut self, level: &Level, config: &MctsConfig, rng: &mut R) {
+        let deadline = level.time.map(|t| Instant::now() + t);
+        let mut n = 0;
+        loop {
+            match deadline {
+                Some(d) if Instant::now() >= d => break,
+                None if n >= config.iterations => break,
+                _ => (),
+            }
+            self.iterate(config, rng);
+            n += 1;
+        }
+    }
+
+    /// 访问次数最多的一步
+    pub fn best_step(&self) -> Option<S> {
+        self.nodes.first()?.children.iter()
+            .max_by_key(|c| self.nodes[**c].visits)
+            .and_then(|c| self.nodes[*c].step)
+    }
+
+    fn select(&self, idx: usize, c: f32) -> usize {
+        let ln = (self.nodes[idx].visits.max(1) as f32).ln();
+        let uct = |i: &usize| {
+            let n = &self.nodes[*i];
+            let v = n.visits.max(1) as f32;
+            n.reward / v + c * (ln / v).sqrt()
+        };
+        *self.nodes[idx].children.iter()
+            .max_by(|a, b| uct(a).total_cmp(&uct(b)))
+            .unwrap()
+    }
+
+    /// 选择、展开、模拟、回传
+    fn iterate<R: Rng>(&mut self, config: &MctsConfig, rng: &mut R) {
+        let mut board = self.board.clone().unwrap();
+        let mut idx = 0;
+        while self.nodes[idx].untried.is_empty() && !self.nodes[idx].children.is_empty() {
+            idx = self.select(idx, config.exploration);
+            board.put(self.nodes[idx].step.unwrap());
+        }
+
+        if let Some(s) = self.nodes[idx].untried.pop() {
+            board.put(s);
+            let player = self.nodes[idx].player.rev();
+            let child = self.nodes.len();
+            self.nodes.push(Self::new_node(&board, player, Some(s), Some(idx)));
+            self.nodes[idx].children.push(child);
+            idx = child;
+        }
+
+        let winner = playout(board, self.nodes[idx].player, config, rng);
+        let mut cur = Some(idx);
+        while let Some(i) = cur {
+            let node = &mut self.nodes[i];
+            let mover = node.player.rev();
+            node.visits += 1;
+            node.reward += match winner {
+                Some(w) if w == mover => 1.0,
+                Some(_) => 0.0,
+                None => 0.5,
+            };
+            cur = node.parent;
+        }
+    }
+}
+
+/// 模拟到终局，返回胜者，和棋为None；超过步数上限时按评分判定
+fn playout<B: AI<S>, S: Step, R: Rng>(mut board: B, mut player: Player,
+    config: &MctsConfig, rng: &mut R) -> Option<Player> {
+
+    for _ in 0..config.max_playout {
+        if board.over() != GameState::Running {
+            break;
+        }
+        let steps = board.get_possible_steps(player, u8::MAX);
+        let step = match config.playout {
+            Playout::Random => steps.choose(rng).copied(),
+            Playout::Heuristic => heuristic_step(&board, player, &steps, rng),
+        };
+        match step {
+            Some(s) => board.put(s),
+            None => break,
+        };
+        player = player.rev();
+    }
+    match board.over() {
+        GameState::Over(OutCome::Winer(p)) => Some(p),
+        GameState::Over(OutCome::Timeout(p)) => Some(p.rev()),
+        GameState::Over(OutCome::Draw) => None,
+        GameState::Running => match board.score(Player(0)) {
+            s if s > 0 => Some(Player(0)),
+            s if s < 0 => Some(Player(1)),
+            _ => None,
+        },
+    }
+}
+
+fn heuristic_step<B: AI<S>, S: Step, R: Rng>(board: &B, player: Player, steps: &[S], rng: &mut R) -> Option<S> {
+    let scored: Vec<(S, i32)> = steps.iter()
+        .filter_map(|s| board.copy_put(*s).map(|b| (*s, b.score(player))))
+        .collect();
+    let best = scored.iter().map(|s| s.1).max()?;
+    let top: Vec<S> = scored.iter().filter(|s| s.1 == best).map(|s| s.0).collect();
+    top.choose(rng).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::{base::{Board, GameType}, tic_tac_toe::{TTTBoard, TTTStep}, goT::{GoTBoard, GoTStep}, record};
+
+    fn ttt_game(line: &str) -> Game<TTTBoard, TTTStep> {
+        let mut game = Game::new(TTTBoard::new(), GameType::Put);
+        for s in record::parse_game(line, &game.board.clone()).unwrap() {
+            game.step(s);
+        }
+        game
+    }
+
+    #[test]
+    fn finds_win_and_block_on_ttt() {
+        let mut rng = StdRng::seed_from_u64(1);
+        // 0走(0,0)(0,1)，轮到0，(0,2)即胜
+        let game = ttt_game("0,0 1,1 0,1 2,2");
+        let step = Mcts::new().game_best_step(&game, &Level::FULL, &MctsConfig::DEFAULT, &mut rng);
+        assert_eq!(step.unwrap().to_pos(), (0, 2));
+        // 轮到1，必须堵(0,2)
+        let game = ttt_game("0,0 1,1 0,1");
+        let step = Mcts::new().game_best_step(&game, &Level::FULL, &MctsConfig::DEFAULT, &mut rng);
+        assert_eq!(step.unwrap().to_pos(), (0, 2));
+    }
+
+    #[test]
+    fn reuses_subtree() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut game = ttt_game("1,1");
+        let mut tree = Mcts::new();
+        let step = tree.game_best_step(&game, &Level::FULL, &MctsConfig::DEFAULT, &mut rng).unwrap();
+        game.step(step);
+        let reply = TTTStep::new_put_step(if step.to_pos() == (0, 0) { (2, 2) } else { (0, 0) }, Player(0));
+        game.step(reply);
+        tree.sync(&game);
+        // 两步之后的子树被保留，而不是新建
+        assert!(tree.nodes[0].visits > 0);
+        assert_eq!(tree.nodes[0].player, game.curr_player);
+        assert_eq!(tree.nodes.len(), 1 + tree.nodes[0].children.len() + tree.nodes[1..].iter().map(|n| n.children.len()).sum::<usize>());
+    }
+
+    #[test]
+    fn plays_got_without_evaluator() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let game: Game<GoTBoard, GoTStep> = Game::new(GoTBoard::new(), GameType::Move);
+        let config = MctsConfig { iterations: 500, ..MctsConfig::DEFAULT };
+        let step = Mcts::new().game_best_step(&game, &Level::FULL, &config, &mut rng);
+        assert!(step.is_some_and(|s| game.board.copy_put(s).is_some()));
+    }
+}
@@ -0,0 +1,209 @@
+// 两个终端之间联网对局的协议
+//
+// 一行一条消息，UTF-8：
+//   SETUP <游戏> <主机执o或x> <用时或-> <局面或->   主机在对方连上后发送，-为默认，局面见position.rs
+//   MOVE <步子>      步子见record.rs，如`7,7`、`0,0-1,0`
+//   CHAT <文字>
+//   RESIGN           认输
+//   UNDO             请求悔棋，撤回自己上一步（已应的话连对方的应手一起）
+//   UNDO OK / UNDO NO  同意/拒绝对方的悔棋
+//   DRAW             求和
+//   DRAW OK / DRAW NO  同意/拒绝对方的求和
+// 连接断开即对局结束。
+
+use std::{io::{self, BufRead, BufReader, Write}, net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, Receiver, TryRecvError}, thread};
+
+use crate::base::Player;
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum Message {
+    Setup { game: String, host: Player, clock: Option<String>, position: Option<String> },
+    Move(String),
+    Chat(String),
+    Resign,
+    Undo,
+    UndoReply(bool),
+    Draw,
+    DrawReply(bool),
+}
+
+fn side_char(p: Player) -> char {
+    if p.0 == 0 { 'o' } else { 'x' }
+}
+
+impl Message {
+    pub fn to_line(&self) -> String {
+        match self {
+            Message::Setup { game, host, clock, position } => format!("SETUP {} {} {} {}",
+                game, side_char(*host), clock.as_deref().unwrap_or("-"), position.as_deref().unwrap_or("-")),
+            Message::Move(s) => format!("MOVE {}", s),
+            Message::Chat(s) => format!("CHAT {}", s.replace('\n', " ")),
+            Message::Resign => "RESIGN".to_string(),
+            Message::Undo => "UNDO".to_string(),
+            Message::UndoReply(ok) => format!("UNDO {}", if *ok { "OK" } else { "NO" }),
+            Message::Draw => "DRAW".to_string(),
+            Message::DrawReply(ok) => format!("DRAW {}", if *ok { "OK" } else { "NO" }),
+        }
+    }
+
+    pub fn parse(line: &str) -> Option<Message> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let (cmd, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match cmd {
+            "SETUP" => {
+                let mut words = rest.splitn(4, ' ');
+                let game = words.next()?.to_string();
+                let host = match words.next()? {
+                    "o" => Player(0),
+                    "x" => Player(1),
+                    _ => return None,
+                };
+                // 缺字段为None，写`-`为Some(None)
+                let mut optional = || words.next().map(|w| Some(w).filter(|w| *w != "-").map(str::to_string));
+                let clock = optional()?;
+                Some(Message::Setup { game, host, clock, position: optional()? })
+            },
+            "MOVE" if !rest.is_empty() => Some(Message::Move(rest.to_string())),
+            "CHAT" => Some(Message::Chat(rest.to_string())),
+            "RESIGN" => Some(Message::Resign),
+            "UNDO" => match rest {
+                "" => Some(Message::Undo),
+                "OK" => Some(Message::UndoReply(true)),
+                "NO" => Some(Message::UndoReply(false)),
+                _ => None,
+            },
+            "DRAW" => match rest {
+                "" => Some(Message::Draw),
+                "OK" => Some(Message::DrawReply(true)),
+                "NO" => Some(Message::DrawReply(false)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// 对方的连接。收消息在单独的线程里，界面循环里用poll取，不阻塞
+pub struct Peer {
+    stream: TcpStream,
+    incoming: Receiver<Message>,
+}
+
+impl Peer {
+    /// 在addr上等一个对手连进来
+    pub fn host<A: ToSocketAddrs>(addr: A) -> io::Result<Peer> {
+        Self::accept(&TcpListener::bind(addr)?)
+    }
+
+    pub fn accept(listener: &TcpListener) -> io::Result<Peer> {
+        let (stream, _) = listener.accept()?;
+        Self::new(stream)
+    }
+
+    pub fn join<A: ToSocketAddrs>(addr: A) -> io::Result<Peer> {
+        Self::new(TcpStream::connect(addr)?)
+    }
+
+    fn new(stream: TcpStream) -> io::Result<Peer> {
+        let reader = BufReader::new(stream.try_clone()?);
+        let (tx, incoming) = mpsc::channel();
+        // 对方断开或本方退出时线程结束，发送端随之关闭
+        thread::spawn(move || {
+            for line in reader.lines() {
+                let Ok(line) = line else { break };
+                // 不认识的消息跳过，兼容以后加的命令
+                if let Some(m) = Message::parse(&line) {
+                    if tx.send(m).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(Peer { stream, incoming })
+    }
+
+    pub fn send(&self, message: &Message) -> io::Result<()> {
+        (&self.stream).write_all((message.to_line() + "\n").as_bytes())
+    }
+
+    /// 取一条已收到的消息；没有时为Ok(None)，连接已断开时为Err
+    pub fn poll(&self) -> Result<Option<Message>, io::Error> {
+        match self.incoming.try_recv() {
+            Ok(m) => Ok(Some(m)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(io::Error::new(io::ErrorKind::ConnectionAborted, "对方已断开")),
+        }
+    }
+
+    /// 等到下一条消息，用于开局前交换设置
+    pub fn recv(&self) -> io::Result<Message> {
+        self.incoming.recv().map_err(|_| io::Error::new(io::ErrorKind::ConnectionAborted, "对方已断开"))
+    }
+}
+
+/// 收消息的线程还拿着连接的副本，要显式关掉对方才知道
+impl Drop for Peer {
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    #[test]
+    fn lines_round_trip() {
+        for m in [
+            Message::Setup { game: "gobang".to_string(), host: Player(1), clock: Some("5m+3s".to_string()),
+                position: Some("15/15/15/15/15/15/15/7o7/15/15/15/15/15/15/15 x".to_string()) },
+            Message::Setup { game: "ttt".to_string(), host: Player(0), clock: None, position: None },
+            Message::Move("0,0-1,0".to_string()),
+            Message::Chat("你好 good game".to_string()),
+            Message::Resign,
+            Message::Undo,
+            Message::UndoReply(true),
+            Message::UndoReply(false),
+            Message::Draw,
+            Message::DrawReply(true),
+        ] {
+            assert_eq!(Message::parse(&m.to_line()), Some(m.clone()), "{}", m.to_line());
+        }
+        for bad in ["", "MOVE", "SETUP ttt y - 3/3/3 o", "SETUP ttt o -", "UNDO maybe", "HELLO"] {
+            assert!(Message::parse(bad).is_none(), "{}", bad);
+        }
+    }
+
+    /// 收到消息前最多等一会
+    fn wait(peer: &Peer) -> Result<Option<Message>, io::Error> {
+        let t = Instant::now();
+        loop {
+            match peer.poll() {
+                Ok(None) if t.elapsed() < Duration::from_secs(5) => thread::sleep(Duration::from_millis(5)),
+                r => return r,
+            }
+        }
+    }
+
+    #[test]
+    fn exchange_on_localhost() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let guest = thread::spawn(move || Peer::join(addr).unwrap());
+        let host = Peer::accept(&listener).unwrap();
+        let guest = guest.join().unwrap();
+
+        host.send(&Message::Move("7,7".to_string())).unwrap();
+        host.send(&Message::Chat("hi".to_string())).unwrap();
+        assert_eq!(guest.recv().unwrap(), Message::Move("7,7".to_string()));
+        assert_eq!(wait(&guest).unwrap(), Some(Message::Chat("hi".to_string())));
+        guest.send(&Message::Resign).unwrap();
+        assert_eq!(wait(&host).unwrap(), Some(Message::Resign));
+
+        drop(guest);
+        assert!(wait(&host).is_err());
+    }
+}
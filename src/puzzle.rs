@@ -0,0 +1,269 @@
+// 五子棋连续冲四题的文件格式
+//
+// 一行一题：`局面;冲四步数;答案;状态`。局面见position.rs，轮到的一方为进攻方；
+// 答案为双方交替的步子，从进攻方开始，最后一步成五；状态为空（未做）、solved或failed。
+// 例：`/15/15/8x6/8o6/8o6/8o6/3xooo8 o;1;7,8 7,7 8,8;`
+
+use std::{collections::HashSet, fs, io, path::Path};
+
+use rand::Rng;
+
+use crate::{base::{Board, Player, Step, GameState, OutCome, Symmetry}, ai::{self, AI, Level, SearchCtx},
+    gobang::{self, GoBoard, GoStep}, position, record};
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Default)]
+pub enum Status {
+    #[default]
+    Unsolved,
+    Solved,
+    Failed,
+}
+
+impl Status {
+    fn name(&self) -> &'static str {
+        match self {
+            Status::Unsolved => "",
+            Status::Solved => "solved",
+            Status::Failed => "failed",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Status> {
+        [Status::Unsolved, Status::Solved, Status::Failed].into_iter().find(|s| s.name() == name)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Puzzle {
+    pub position: String,
+    /// 最多冲四几步（不含最后成五的一步）
+    pub fours: u8,
+    pub solution: Vec<GoStep>,
+    pub status: Status,
+}
+
+impl Puzzle {
+    /// 题目的局面与进攻方
+    pub fn board(&self) -> (GoBoard, Player) {
+        position::parse(&self.position, &GoBoard::new()).expect("puzzle position")
+    }
+
+    pub fn goal(&self) -> String {
+        let (_, attacker) = self.board();
+        format!("{}先，连续冲四{}步内取胜", attacker.0, self.fours)
+    }
+
+    fn to_line(&self) -> String {
+        format!("{};{};{};{}", self.position, self.fours, record::game_to_string(&self.solution), self.status.name())
+    }
+
+    /// 局面、答案要合法，答案要真能取胜
+    fn parse(line: &str) -> Option<Puzzle> {
+        let mut fields = line.split(';');
+        let position = fields.next()?.trim().to_string();
+        let fours = fields.next()?.trim().parse().ok()?;
+        let (mut board, attacker) = position::parse(&position, &GoBoard::new())?;
+        let mut p = attacker;
+        let mut solution = vec![];
+        for word in fields.next()?.split_whitespace() {
+            let s: GoStep = record::parse_step(word, p)?;
+            if !board.put(s) {
+                return None;
+            }
+            solution.push(s);
+            p = p.rev();
+        }
+        if board.over() != GameState::Over(OutCome::Winer(attacker)) {
+            return None;
+        }
+        let status = Status::from_name(fields.next().unwrap_or("").trim())?;
+        Some(Puzzle { position, fours, solution, status })
+    }
+}
+
+/// 一组题目，做完后连同状态写回文件
+pub struct PuzzleSet {
+    pub puzzles: Vec<Puzzle>,
+}
+
+impl PuzzleSet {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut puzzles = vec![];
+        for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let p = Puzzle::parse(line).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("line {}: bad puzzle", i + 1))
+            })?;
+            puzzles.push(p);
+        }
+        Ok(PuzzleSet { puzzles })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let text: String = self.puzzles.iter().map(|p| p.to_line() + "\n").collect();
+        fs::write(path, text)
+    }
+
+    pub fn count(&self, status: Status) -> usize {
+        self.puzzles.iter().filter(|p| p.status == status).count()
+    }
+}
+
+/// 走一步之后的结果
+#[derive(PartialEq, Debug)]
+pub enum Progress {
+    /// 对方被迫应了这一步，继续
+    Continue(GoStep),
+    Solved,
+    Failed(&'static str),
+}
+
+/// 做题的过程：人走进攻方，电脑按冲四求解的结果完美应对
+pub struct Attempt {
+    pub board: GoBoard,
+    pub attacker: Player,
+    pub fours_left: u8,
+    pub steps: Vec<GoStep>,
+}
+
+impl Attempt {
+    pub fn new(puzzle: &Puzzle) -> Self {
+        let (board, attacker) = puzzle.board();
+        Attempt { board, attacker, fours_left: puzzle.fours, steps: vec![] }
+    }
+
+    /// 进攻方在pos落子，落不下时返回None
+    pub fn play(&mut self, pos: (u8, u8)) -> Option<Progress> {
+        let step = GoStep::new_put_step(pos, self.attacker);
+        if !self.board.put(step) {
+            return None;
+        }
+        self.steps.push(step);
+        if self.board.over() == GameState::Over(OutCome::Winer(self.attacker)) {
+            return Some(Progress::Solved);
+        }
+        if self.fours_left == 0 {
+            return Some(Progress::Failed("冲四步数用完了"));
+        }
+        let reply = match gobang::forced_reply(&self.board, self.attacker) {
+            Some(r) => r,
+            None => return Some(Progress::Failed("这步不是冲四")),
+        };
+        self.fours_left -= 1;
+        self.board.put(reply);
+        self.steps.push(reply);
+        if self.board.over() != GameState::Running {
+            return Some(Progress::Failed("对方成五"));
+        }
+        if gobang::solve_vcf(&self.board, self.attacker, self.fours_left).is_none() {
+            return Some(Progress::Failed("已经没有连续冲四的胜法"));
+        }
+        Some(Progress::Continue(reply))
+    }
+}
+
+/// 轮到attacker走时，冲四不超过max_fours步、且第一步唯一的最短胜法可以出题
+pub fn find_puzzle(board: &GoBoard, attacker: Player, max_fours: u8) -> Option<Puzzle> {
+    let solution = gobang::solve_vcf(board, attacker, max_fours)?;
+    // 能直接成五的不算题
+    let fours = (solution.len() / 2) as u8;
+    if fours == 0 || gobang::winning_fours(board, attacker, fours).len() != 1 {
+        return None;
+    }
+    Some(Puzzle { position: position::to_string(board, attacker), fours, solution, status: Status::Unsolved })
+}
+
+/// 自对弈出题：前几步随机，之后电脑按EASY难度走，每局取第一个能出题的局面
+pub fn generate<R: Rng>(games: usize, max_fours: u8, rng: &mut R) -> PuzzleSet {
+    const RANDOM_PLY: usize = 4;
+    let mut puzzles: Vec<Puzzle> = vec![];
+    // 出过的局面，对称的算同一题
+    let mut seen = HashSet::new();
+    for _ in 0..games {
+        let mut board = GoBoard::new();
+        let mut p = Player(0);
+        let mut ply = 0;
+        while board.over() == GameState::Running {
+            if let Some(puzzle) = find_puzzle(&board, p, max_fours) {
+                if seen.insert(board.canonical_key(p)) {
+                    puzzles.push(puzzle);
+                }
+                break;
+            }
+            let step = if ply < RANDOM_PLY {
+                let v = board.get_possible_steps(p, u8::MAX);
+                let n = v.len().min(8);
+                if n == 0 { None } else { Some(v[rng.gen_range(0..n)]) }
+            } else {
+                ai::search_best_step(&board, p, &Level::EASY, SearchCtx::new(None))
+            };
+            match step {
+                Some(s) if board.put(s) => (),
+                _ => break,
+            }
+            p = p.rev();
+            ply += 1;
+        }
+    }
+    PuzzleSet { puzzles }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 两步冲四取胜，见gobang::vcf的chain_of_fours；先冲(6,8)或(7,8)都行
+    const CHAIN: &str = "/15/15/8xxx4/8o6/8o6/4xooo7/3xooo8/5x9 o;2;6,8 6,9 7,8 7,7 8,8;";
+
+    fn chain() -> Puzzle {
+        Puzzle::parse(CHAIN).unwrap()
+    }
+
+    #[test]
+    fn finds_unique_forced_win() {
+        let (b, p) = position::parse("/15/15/8x6/8o6/8o6/8o6/3xooo8 o", &GoBoard::new()).unwrap();
+        let puzzle = find_puzzle(&b, p, 3).unwrap();
+        assert_eq!(puzzle.fours, 1);
+        assert_eq!(puzzle.solution[0].to_pos(), (7, 8));
+        assert_eq!(puzzle.goal(), "0先，连续冲四1步内取胜");
+        // 第一步不唯一的不出题
+        let (b, p) = chain().board();
+        assert!(find_puzzle(&b, p, 3).is_none());
+        // 能直接成五的不出题
+        let (b, p) = position::parse("/7/.oooo/ o", &GoBoard::new()).unwrap();
+        assert!(find_puzzle(&b, p, 3).is_none());
+    }
+
+    #[test]
+    fn line_round_trip() {
+        let mut puzzle = chain();
+        assert_eq!(puzzle.to_line(), CHAIN);
+        puzzle.status = Status::Solved;
+        let back = Puzzle::parse(&puzzle.to_line()).unwrap();
+        assert_eq!(back.status, Status::Solved);
+        // 答案不能取胜的不收
+        assert!(Puzzle::parse("/15/15/8xxx4/8o6/8o6/4xooo7/3xooo8/5x9 o;2;6,8 6,9;").is_none());
+    }
+
+    #[test]
+    fn attempt_follows_solution() {
+        let puzzle = chain();
+        let mut a = Attempt::new(&puzzle);
+        let steps: Vec<_> = puzzle.solution.iter().map(|s| s.to_pos()).collect();
+        assert_eq!(a.play(steps[0]), Some(Progress::Continue(puzzle.solution[1])));
+        assert_eq!(a.play(steps[2]), Some(Progress::Continue(puzzle.solution[3])));
+        assert_eq!(a.play(steps[4]), Some(Progress::Solved));
+    }
+
+    #[test]
+    fn attempt_fails() {
+        let puzzle = chain();
+        // 落在已有子的格子上不算一步
+        assert_eq!(Attempt::new(&puzzle).play((3, 8)), None);
+        assert_eq!(Attempt::new(&puzzle).play((0, 0)), Some(Progress::Failed("这步不是冲四")));
+        // (7,7)也是冲四，但对方挡在(7,8)后竖线就断了
+        assert_eq!(Attempt::new(&puzzle).play((7, 7)), Some(Progress::Failed("已经没有连续冲四的胜法")));
+    }
+}
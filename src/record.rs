@@ -0,0 +1,103 @@
+// 棋谱的文本格式
+//
+// 一步棋：落子为`x,y`，走棋为`x,y-x,y`；一局棋为以空格分隔的步子，一行一局。
+// 步子属于谁不记录，从先手开始轮流。
+
+use std::collections::HashSet;
+
+use crate::base::{Board, Player, Step, Symmetry, game_key};
+
+pub fn step_to_string<S: Step>(step: &S) -> String {
+    let (x, y) = step.to_pos();
+    match step.origin_pos() {
+        Some((fx, fy)) => format!("{},{}-{},{}", fx, fy, x, y),
+        None => format!("{},{}", x, y),
+    }
+}
+
+pub fn parse_pos(s: &str) -> Option<(u8, u8)> {
+    let (x, y) = s.trim().split_once(',')?;
+    Some((x.trim().parse().ok()?, y.trim().parse().ok()?))
+}
+
+pub fn parse_step<S: Step>(s: &str, p: Player) -> Option<S> {
+    match s.split_once('-') {
+        Some((from, to)) => Some(S::new_move_step(parse_pos(from)?, parse_pos(to)?, p)),
+        None => Some(S::new_put_step(parse_pos(s)?, p)),
+    }
+}
+
+pub fn game_to_string<S: Step>(steps: &[S]) -> String {
+    steps.iter().map(step_to_string).collect::<Vec<_>>().join(" ")
+}
+
+/// 解析一局棋，并在棋盘上验证每一步，出错返回None
+pub fn parse_game<B: Board<S>, S: Step>(line: &str, start: &B) -> Option<Vec<S>> {
+    let mut board = start.clone();
+    let mut p = Player(0);
+    let mut steps = vec![];
+    for word in line.split_whitespace() {
+        let s = parse_step(word, p)?;
+        if !board.put(s) {
+            return None;
+        }
+        steps.push(s);
+        p = p.rev();
+    }
+    Some(steps)
+}
+
+/// 去掉重复的对局，对称的两局也算重复，保留先出现的；返回去掉的局数
+pub fn dedup<B: Symmetry<S>, S: Step>(start: &B, games: &mut Vec<Vec<S>>) -> usize {
+    let n = games.len();
+    let mut seen = HashSet::new();
+    games.retain(|steps| seen.insert(game_key(start, steps)));
+    n - games.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tic_tac_toe::{TTTBoard, TTTStep}, goT::{GoTBoard, GoTStep}};
+
+    #[test]
+    fn steps_round_trip() {
+        let s = TTTStep::new_put_step((2, 1), Player(1));
+        assert_eq!(step_to_string(&s), "2,1");
+        let back: TTTStep = parse_step("2,1", Player(1)).unwrap();
+        assert_eq!((back.to_pos(), back.origin_pos(), back.who()), ((2, 1), None, Player(1)));
+
+        let s = GoTStep::new_move_step((0, 3), (1, 3), Player(0));
+        assert_eq!(step_to_string(&s), "0,3-1,3");
+        let back: GoTStep = parse_step(" 0,3-1, 3", Player(0)).unwrap();
+        assert_eq!((back.to_pos(), back.origin_pos()), ((1, 3), Some((0, 3))));
+
+        assert_eq!(parse_pos("1,x"), None);
+        assert_eq!(parse_pos("12"), None);
+    }
+
+    #[test]
+    fn games_round_trip() {
+        let start = GoTBoard::new();
+        let line = "0,0-1,0 3,3-2,3 1,0-1,1";
+        let steps = parse_game(line, &start).unwrap();
+        assert_eq!(steps.iter().map(|s| s.who()).collect::<Vec<_>>(), [Player(0), Player(1), Player(0)]);
+        assert_eq!(game_to_string(&steps), line);
+        // 不合法的一步（走到斜线、走对方的子）整局作废
+        assert!(parse_game("0,0-1,1", &start).is_none());
+        assert!(parse_game("3,3-2,3", &start).is_none());
+        assert!(parse_game::<TTTBoard, TTTStep>("1,1 1,1", &TTTBoard::new()).is_none());
+        assert_eq!(parse_game::<TTTBoard, TTTStep>("", &TTTBoard::new()).map(|g| g.len()), Some(0));
+    }
+
+    #[test]
+    fn dedup_symmetric_games() {
+        let start = TTTBoard::new();
+        let mut games: Vec<Vec<TTTStep>> = ["0,0 1,1", "2,2 1,1", "0,1 1,1", "0,0 1,1", "1,1 0,0"].iter()
+            .map(|l| parse_game(l, &start).unwrap()).collect();
+        // 第2局是第1局旋转180度，第4局与第1局相同
+        assert_eq!(dedup(&start, &mut games), 2);
+        let left: Vec<_> = games.iter().map(|g| game_to_string(g)).collect();
+        assert_eq!(left, ["0,0 1,1", "0,1 1,1", "1,1 0,0"]);
+    }
+}
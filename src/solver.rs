@@ -0,0 +1,315 @@
+use std::{collections::{HashMap, VecDeque}, fs::File, io::{self, Read, Write, BufReader, BufWriter}, path::Path};
+
+use crate::{base::{Player, GameState, OutCome, Step, Symmetry}, ai::AI};
+
+/// 可以穷举求解的棋盘
+pub trait Solve<S: Step>: AI<S> + Symmetry<S> {}
+
+impl<B: AI<S> + Symmetry<S>, S: Step> Solve<S> for B {}
+
+/// 把格子按三进制编码成整数，不同局面一定不同，且不依赖哈希实现，可以写入文件
+pub fn encode<I: Iterator<Item = Option<Player>>>(cells: I) -> u64 {
+    cells.fold(0, |acc, c| acc * 3 + match c {
+        None => 0,
+        Some(p) => p.0 as u64 + 1,
+    })
+}
+
+/// 局面的理论值，以行棋方的角度，数字为距离结束的步数
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Value {
+    Win(u16),
+    Draw,
+    Loss(u16),
+}
+
+impl Value {
+    /// 子局面的值换成父局面行棋方的角度
+    pub fn parent(&self) -> Self {
+        match *self {
+            Value::Win(d) => Value::Loss(d + 1),
+            Value::Loss(d) => Value::Win(d + 1),
+            Value::Draw => Value::Draw,
+        }
+    }
+
+    /// 越大越好：赢得越快越好，输得越慢越好
+    pub fn rank(&self) -> i32 {
+        match *self {
+            Value::Win(d) => 100000 - d as i32,
+            Value::Draw => 0,
+            Value::Loss(d) => -100000 + d as i32,
+        }
+    }
+
+    fn encode(&self) -> u16 {
+        match *self {
+            Value::Draw => 0,
+            Value::Win(d) => 0x4000 | (d & 0x3fff),
+            Value::Loss(d) => 0x8000 | (d & 0x3fff),
+        }
+    }
+
+    fn decode(v: u16) -> Option<Self> {
+        let d = v & 0x3fff;
+        match v >> 14 {
+            0 => Some(Value::Draw),
+            1 => Some(Value::Win(d)),
+            2 => Some(Value::Loss(d)),
+            _ => None,
+        }
+    }
+
+    pub fn describe(&self, next: Player) -> String {
+        match *self {
+            Value::Win(d) => format!("Player {} {}步内必胜", next.0, d),
+            Value::Loss(d) => format!("Player {} {}步内必胜", next.rev().0, d),
+            Value::Draw => "和棋".to_string(),
+        }
+    }
+}
+
+/// 查表得到理论值与完美走法，界面只依赖它，不要求棋盘实现Solve
+pub trait Oracle<B, S> {
+    /// 只有走法没有理论值的（如开局库）返回None
+    fn value(&self, _board: &B, _next: Player) -> Option<Value> {
+        None
+    }
+
+    fn best_step(&self, board: &B, next: Player) -> Option<S>;
+}
+
+/// 所有可达局面的理论值
+pub struct Tablebase {
+    table: HashMap<u64, Value>,
+}
+
+/// goT吃子规则修正（横向吃子、胜方）后改为RBT2：旧的goT.tb按旧规则求解，已经不对，必须重新生成。
+/// load遇到旧的魔数会失败，load_or_solve随即重新求解并覆盖文件
+const MAGIC: &[u8; 4] = b"RBT2";
+
+/// 表中的key：标准形的编码，再加上行棋方
+pub fn table_key<B: Solve<S>, S: Step>(board: &B, next: Player) -> u64 {
+    encode(board.sym_cells(board.canonical_sym()).into_iter()) * 2 + next.0 as u64
+}
+
+impl Tablebase {
+    /// 从初始局面出发，穷举所有可达局面并逆推求值
+    /// 不考虑无吃子和棋规则，循环局面视为和棋
+    pub fn solve<B: Solve<S>, S: Step>(start: &B, next: Player) -> Self {
+        // 建图
+        let mut index = HashMap::new();
+        let mut nodes = vec![(start.canonical().0, next)];
+        let mut children: Vec<Vec<usize>> = vec![];
+        index.insert(table_key(start, next), 0);
+
+        let mut i = 0;
+        while i < nodes.len() {
+            let (board, p) = nodes[i].clone();
+            let mut v = vec![];
+            if board.over() == GameState::Running {
+                for s in board.get_possible_steps(p, u8::MAX) {
+                    let nb = board.copy_put(s).unwrap();
+                    let key = table_key(&nb, p.rev());
+                    let idx = *index.entry(key).or_insert_with(|| {
+                        // 对称的局面只展开一次，存标准形
+                        nodes.push((nb.canonical().0, p.rev()));
+                        nodes.len() - 1
+                    });
+                    v.push(idx);
+                }
+            }
+            children.push(v);
+            i += 1;
+        }
+
+        let mut parents = vec![vec![]; nodes.len()];
+        for (i, v) in children.iter().enumerate() {
+            for c in v {
+                parents[*c].push(i);
+            }
+        }
+
+        // 逆推，按距离从小到大处理
+        let mut values: Vec<Option<Value>> = vec![None; nodes.len()];
+        let mut remain: Vec<usize> = children.iter().map(|v| v.len()).collect();
+        let mut queue = VecDeque::new();
+        for (i, (board, p)) in nodes.iter().enumerate() {
+            values[i] = match board.over() {
+                GameState::Over(OutCome::Winer(w)) => {
+                    Some(if w == *p {Value::Win(0)} else {Value::Loss(0)})
+                },
+                GameState::Over(OutCome::Timeout(w)) => {
+                    Some(if w == *p {Value::Loss(0)} else {Value::Win(0)})
+                },
+                GameState::Over(OutCome::Draw) => Some(Value::Draw),
+                // 无路可走视为和棋
+                GameState::Running if remain[i] == 0 => Some(Value::Draw),
+                GameState::Running => None,
+            };
+            if let Some(Value::Win(_) | Value::Loss(_)) = values[i] {
+                queue.push_back(i);
+            }
+        }
+
+        while let Some(c) = queue.pop_front() {
+            let cv = values[c].unwrap();
+            for &p in &parents[c] {
+                if values[p].is_some() {
+                    continue;
+                }
+                match cv {
+                    Value::Loss(_) => {
+                        values[p] = Some(cv.parent());
+                        queue.push_back(p);
+                    },
+                    _ => {
+                        remain[p] -= 1;
+                        if remain[p] == 0 {
+                            values[p] = Some(cv.parent());
+                            queue.push_back(p);
+                        }
+                    },
+                }
+            }
+        }
+
+        let mut table = HashMap::new();
+        for (key, i) in index {
+            table.insert(key, values[i].unwrap_or(Value::Draw));
+        }
+        Tablebase { table }
+    }
+
+    pub fn get<B: Solve<S>, S: Step>(&self, board: &B, next: Player) -> Option<Value> {
+        self.table.get(&table_key(board, next)).copied()
+    }
+}
+
+impl<B: Solve<S>, S: Step> Oracle<B, S> for Tablebase {
+    fn value(&self, board: &B, next: Player) -> Option<Value> {
+        self.get(board, next)
+    }
+
+    /// 完美走法：赢得最快，输得最慢
+    fn best_step(&self, board: &B, next: Player) -> Option<S> {
+        let mut best = None;
+        let mut rank = i32::MIN;
+        for s in board.get_possible_steps(next, u8::MAX) {
+            let nb = board.copy_put(s).unwrap();
+            if let Some(v) = self.get(&nb, next.rev()) {
+                let r = v.parent().rank();
+                if r > rank {
+                    rank = r;
+                    best = Some(s);
+                }
+            }
+        }
+        best
+    }
+}
+
+impl Tablebase {
+    /// 格式：MAGIC，条目数(u32)，每条为key(u64)与值(u16)，均为小端
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        let mut entries: Vec<_> = self.table.iter().collect();
+        entries.sort_by_key(|e| *e.0);
+
+        w.write_all(MAGIC)?;
+        w.write_all(&(entries.len() as u32).to_le_bytes())?;
+        for (k, v) in entries {
+            w.write_all(&k.to_le_bytes())?;
+            w.write_all(&v.encode().to_le_bytes())?;
+        }
+        w.flush()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let bad = || io::Error::new(io::ErrorKind::InvalidData, "bad tablebase file");
+        let mut r = BufReader::new(File::open(path)?);
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(bad());
+        }
+        let mut n = [0; 4];
+        r.read_exact(&mut n)?;
+
+        let mut table = HashMap::new();
+        for _ in 0..u32::from_le_bytes(n) {
+            let mut k = [0; 8];
+            let mut v = [0; 2];
+            r.read_exact(&mut k)?;
+            r.read_exact(&mut v)?;
+            let v = Value::decode(u16::from_le_bytes(v)).ok_or_else(bad)?;
+            table.insert(u64::from_le_bytes(k), v);
+        }
+        Ok(Tablebase { table })
+    }
+
+    /// 有文件就读取，否则求解后写入文件
+    pub fn load_or_solve<B: Solve<S>, S: Step, P: AsRef<Path>>(path: P, start: &B, next: Player) -> Self {
+        match Self::load(&path) {
+            Ok(t) => t,
+            Err(_) => {
+                let t = Self::solve(start, next);
+                let _ = t.save(&path);
+                t
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{base::Board, tic_tac_toe::{TTTBoard, TTTStep}, position};
+
+    fn ttt(text: &str) -> (TTTBoard, Player) {
+        position::parse(text, &TTTBoard::new()).unwrap()
+    }
+
+    #[test]
+    fn ttt_start_is_draw() {
+        let board = TTTBoard::new();
+        let t = Tablebase::solve(&board, Player(0));
+        assert_eq!(t.get(&board, Player(0)), Some(Value::Draw));
+        // 先占角后，对方只有占中心才不输
+        let b = board.copy_put(TTTStep::new_put_step((0, 0), Player(0))).unwrap();
+        assert_eq!(t.best_step(&b, Player(1)).map(|s| s.to_pos()), Some((1, 1)));
+        assert_eq!(t.get(&b.copy_put(TTTStep::new_put_step((0, 1), Player(1))).unwrap(), Player(0)), Some(Value::Win(5)));
+    }
+
+    #[test]
+    fn finds_win_in_one() {
+        let (board, next) = ttt("oo./xx./3 o");
+        let t = Tablebase::solve(&board, next);
+        assert_eq!(t.get(&board, next), Some(Value::Win(1)));
+        assert_eq!(t.best_step(&board, next).map(|s| s.to_pos()), Some((0, 2)));
+        // 轮到对方时对方先赢
+        let t = Tablebase::solve(&board, next.rev());
+        assert_eq!(t.get(&board, next.rev()), Some(Value::Win(1)));
+        assert_eq!(t.best_step(&board, next.rev()).map(|s| s.to_pos()), Some((1, 2)));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let board = TTTBoard::new();
+        let t = Tablebase::solve(&board, Player(0));
+        let path = std::env::temp_dir().join(format!("rustbang-tb-{}.tb", std::process::id()));
+        t.save(&path).unwrap();
+        let loaded = Tablebase::load(&path);
+        // 魔数不对时读取失败
+        std::fs::write(&path, b"RBT1\0\0\0\0").unwrap();
+        let old = Tablebase::load(&path);
+        let _ = std::fs::remove_file(&path);
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.table, t.table);
+        let (b, next) = ttt("o.x/.o./3 x");
+        assert_eq!(loaded.get(&b, next), t.get(&b, next));
+        assert!(loaded.get(&b, next).is_some());
+        assert!(old.is_err());
+    }
+}
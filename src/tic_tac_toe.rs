@@ -1,6 +1,7 @@
 use std::fmt::Error;
-use crate::{base::{Player, Board, OutCome, GameState, Step}, ai::AI};
+use crate::{base::{Player, Board, OutCome, GameState, Step, Symmetry, Sym, hash_key}, ai::AI};
 use crate::display::Display;
+use crate::position::SetUp;
 
 const SIZE: u8 = 3;
 
@@ -44,6 +45,14 @@ impl Step for TTTStep {
     fn new_move_step(from: (u8, u8), to: (u8, u8), p: Player) -> Self {
         panic!("TTT dont");
     }
+
+    fn to_pos(&self) -> (u8, u8) {
+        (self.piece.pos.0, self.piece.pos.1)
+    }
+
+    fn origin_pos(&self) -> Option<(u8, u8)> {
+        None
+    }
 }
 
 #[derive(Clone)]
@@ -132,16 +141,31 @@ impl Board<TTTStep> for TTTBoard {
         }
         GameState::Running
     }
+
+    fn key(&self) -> u64 {
+        let mut mat = [[None; SIZE as usize]; SIZE as usize];
+        for p in &self.pieces {
+            mat[p.pos.0 as usize][p.pos.1 as usize] = Some(p.belong);
+        }
+        hash_key(&mat)
+    }
+
+    fn pieces_count(&self) -> u16 {
+        self.pieces.len() as u16
+    }
 }
 
 impl AI<TTTStep> for TTTBoard {
-    fn score(&self, player: Player) -> i16 {
+    fn score(&self, player: Player) -> i32 {
         match self.over() {
             GameState::Running => 0,
             GameState::Over(OutCome::Draw) => 0,
             GameState::Over(OutCome::Winer(p)) => {
                 if p == player {32} else {-32}
             },
+            GameState::Over(OutCome::Timeout(p)) => {
+                if p == player {-32} else {32}
+            },
         }
     }
 
@@ -161,6 +185,45 @@ impl AI<TTTStep> for TTTBoard {
     }
 }
 
+impl Symmetry<TTTStep> for TTTBoard {
+    fn side(&self) -> u8 {
+        SIZE
+    }
+
+    fn cells(&self) -> Vec<Option<Player>> {
+        let mut v = vec![None; (SIZE * SIZE) as usize];
+        for p in &self.pieces {
+            v[(p.pos.0 * SIZE + p.pos.1) as usize] = Some(p.belong);
+        }
+        v
+    }
+
+    fn transform(&self, t: Sym) -> Self {
+        TTTBoard {
+            pieces: self.pieces.iter().map(|p| {
+                let (x, y) = t.pos(SIZE, (p.pos.0, p.pos.1));
+                TTTPiece { belong: p.belong, pos: TTTPos(x, y) }
+            }).collect()
+        }
+    }
+
+    fn transform_step(&self, step: &TTTStep, t: Sym) -> TTTStep {
+        let pos = t.pos(SIZE, (step.piece.pos.0, step.piece.pos.1));
+        TTTStep::new_put_step(pos, step.piece.belong)
+    }
+}
+
+impl SetUp<TTTStep> for TTTBoard {
+    fn set_up(&self, cells: &[Option<Player>]) -> Self {
+        TTTBoard {
+            pieces: cells.iter().enumerate().filter_map(|(i, c)| c.map(|p| TTTPiece {
+                belong: p,
+                pos: TTTPos(i as u8 / SIZE, i as u8 % SIZE),
+            })).collect()
+        }
+    }
+}
+
 impl Display for TTTBoard {
     fn to_array(&self) -> Vec<Vec<char>> {
         let mut arr = vec![vec![' '; 3]; 3];
@@ -183,3 +246,45 @@ impl Display for TTTBoard {
         String::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(diagram: &str) -> TTTBoard {
+        crate::position::parse(diagram, &TTTBoard::new()).unwrap().0
+    }
+
+    #[test]
+    fn every_line_wins() {
+        let lines = [
+            "ooo/.../...", ".../ooo/...", ".../.../ooo",
+            "o../o../o..", ".o./.o./.o.", "..o/..o/..o",
+            "o../.o./..o", "..o/.o./o..",
+        ];
+        for line in lines {
+            for (c, p) in [('o', Player(0)), ('x', Player(1))] {
+                let d = line.replace('o', &c.to_string());
+                assert!(board(&d).over() == GameState::Over(OutCome::Winer(p)), "{}", d);
+            }
+        }
+    }
+
+    #[test]
+    fn draw_and_running() {
+        assert!(board("oxo/oxx/xoo").over() == GameState::Over(OutCome::Draw));
+        assert!(board("oxo/oxx/xo.").over() == GameState::Running);
+        assert!(board("oo./xx./...").over() == GameState::Running);
+        // 满盘但有连线算赢
+        assert!(board("ooo/xxo/oxx").over() == GameState::Over(OutCome::Winer(Player(0))));
+    }
+
+    #[test]
+    fn rejects_occupied_and_outside() {
+        let mut b = board("o../.../...");
+        assert!(!b.hand_put(Player(1), 0, 0));
+        assert!(!b.hand_put(Player(1), 3, 0));
+        assert!(b.hand_put(Player(1), 2, 2));
+        assert_eq!(b.pieces_count(), 2);
+    }
+}